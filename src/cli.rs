@@ -0,0 +1,219 @@
+use crate::{config::ConfigDuration, env::SelectedEnv};
+use anyhow::Result;
+
+/// Commands built into ds, e.g. `ds logs`, they are run before matching the ds files
+/// - A ds file can't have a top level command or alias with one of these names
+pub const BUILTIN_COMMANDS: &[&str] = &[
+    "logs", "history", "again", "!!", "start", "stop", "restart", "status", "wait", "run", "env",
+];
+
+/// Options for ds itself, parsed from the arguments before the command keys
+///
+/// Everything after the first argument that is not an option (or after `--`)
+/// is left untouched, so extra args are still passed on to the command.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CliOptions {
+    /// Environment to run the command with, instead of matching it from the args
+    pub env: Option<String>,
+    /// Time after which the command is terminated, overrides the configured timeout
+    pub timeout: Option<ConfigDuration>,
+    /// Re-run the command when the watched files change
+    pub watch: bool,
+    /// Run commands that have to be confirmed, without asking
+    pub yes: bool,
+    /// Run commands even if their outputs are up to date
+    pub force: bool,
+    /// Run the command once for each of its environments
+    pub all_envs: bool,
+    /// Run the command once for each of these environments
+    pub envs: Option<Vec<String>>,
+    /// Run the command for the environments at the same time
+    pub parallel: bool,
+}
+
+/// Get the value of an option, either from `--option=value` or the next argument
+fn option_value<'a>(
+    name: &str,
+    inline: Option<&'a str>,
+    args: &mut impl Iterator<Item = &'a String>,
+) -> Result<String> {
+    match inline {
+        Some(value) => Ok(value.to_string()),
+        None => args
+            .next()
+            .map(|value| value.to_string())
+            .ok_or_else(|| anyhow::anyhow!("Missing value for option '{}'", name)),
+    }
+}
+
+impl CliOptions {
+    /// Parse the leading options, returning them with the remaining args
+    pub fn parse(args: &[String]) -> Result<(Self, &[String])> {
+        let mut options = CliOptions::default();
+        let mut iter = args.iter();
+        let mut consumed = 0;
+
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with('-') {
+                break;
+            }
+
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+
+            match name {
+                "--" => {
+                    consumed += 1;
+                    break;
+                }
+                "--env" | "-e" => options.env = Some(option_value(name, inline, &mut iter)?),
+                "--timeout" => {
+                    options.timeout = Some(option_value(name, inline, &mut iter)?.parse()?)
+                }
+                "--watch" | "-w" if inline.is_none() => options.watch = true,
+                "--yes" | "-y" if inline.is_none() => options.yes = true,
+                "--force" | "-f" if inline.is_none() => options.force = true,
+                "--all-envs" if inline.is_none() => options.all_envs = true,
+                "--envs" => {
+                    let value = option_value(name, inline, &mut iter)?;
+                    options.envs = Some(
+                        value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|env| !env.is_empty())
+                            .map(String::from)
+                            .collect(),
+                    )
+                }
+                "--parallel" | "-p" if inline.is_none() => options.parallel = true,
+                _ => return Err(anyhow::anyhow!("Unknown option: {}", arg)),
+            }
+
+            consumed = args.len() - iter.len();
+        }
+
+        if options.parallel && !options.all_envs && options.envs.is_none() {
+            return Err(anyhow::anyhow!(
+                "--parallel runs the envs at the same time, it needs --all-envs or --envs"
+            ));
+        }
+
+        Ok((options, &args[consumed..]))
+    }
+
+    /// Get the environment to use, the `--env` option wins over the `DS_ENV` variable
+    pub fn selected_env(&self) -> Option<SelectedEnv> {
+        match &self.env {
+            Some(env) => Some(SelectedEnv::Flag(env.clone())),
+            None => std::env::var("DS_ENV")
+                .ok()
+                .filter(|env| !env.is_empty())
+                .map(SelectedEnv::Var),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_options() {
+        struct Case {
+            name: &'static str,
+            args: Vec<&'static str>,
+            expected_env: Option<&'static str>,
+            expected_rest: Vec<&'static str>,
+        }
+
+        let cases = [
+            Case {
+                name: "No options",
+                args: vec!["app", "build", "--flag"],
+                expected_env: None,
+                expected_rest: vec!["app", "build", "--flag"],
+            },
+            Case {
+                name: "Long env option",
+                args: vec!["--env", "prod", "app", "build"],
+                expected_env: Some("prod"),
+                expected_rest: vec!["app", "build"],
+            },
+            Case {
+                name: "Short env option, inline value",
+                args: vec!["-e=dev", "app"],
+                expected_env: Some("dev"),
+                expected_rest: vec!["app"],
+            },
+            Case {
+                name: "Options after the keys are left alone",
+                args: vec!["app", "--env", "prod"],
+                expected_env: None,
+                expected_rest: vec!["app", "--env", "prod"],
+            },
+            Case {
+                name: "Separator ends the options",
+                args: vec!["-e", "prod", "--", "-weird-key"],
+                expected_env: Some("prod"),
+                expected_rest: vec!["-weird-key"],
+            },
+        ];
+
+        for case in cases {
+            let args: Vec<String> = case.args.iter().map(|s| s.to_string()).collect();
+            let (options, rest) = CliOptions::parse(&args).unwrap();
+
+            assert_eq!(options.env.as_deref(), case.expected_env, "{}", case.name);
+            assert_eq!(rest, case.expected_rest, "{}", case.name);
+        }
+
+        let args = vec!["--env".to_string()];
+        let err = CliOptions::parse(&args).unwrap_err();
+        assert!(err.to_string().contains("Missing value for option '--env'"));
+
+        let args = vec!["--timeout=1m 30s".to_string(), "test".to_string()];
+        let (options, rest) = CliOptions::parse(&args).unwrap();
+        assert_eq!(
+            options.timeout,
+            Some(ConfigDuration(Duration::from_secs(90)))
+        );
+        assert_eq!(rest, ["test"]);
+
+        let args = vec!["-w".to_string(), "-e".to_string(), "dev".to_string()];
+        let (options, rest) = CliOptions::parse(&args).unwrap();
+        assert!(options.watch);
+        assert!(rest.is_empty());
+
+        let args = vec!["-w".to_string(), "--yes".to_string(), "-f".to_string()];
+        let (options, rest) = CliOptions::parse(&args).unwrap();
+        assert!(options.watch);
+        assert!(options.yes);
+        assert!(options.force);
+        assert!(rest.is_empty());
+
+        let args = vec![
+            "--envs=dev, prod".to_string(),
+            "-p".to_string(),
+            "build".to_string(),
+        ];
+        let (options, rest) = CliOptions::parse(&args).unwrap();
+        assert_eq!(
+            options.envs,
+            Some(vec!["dev".to_string(), "prod".to_string()])
+        );
+        assert!(options.parallel);
+        assert!(!options.all_envs);
+        assert_eq!(rest, ["build"]);
+
+        let args = vec!["-p".to_string(), "build".to_string()];
+        let err = CliOptions::parse(&args).unwrap_err();
+        assert!(err.to_string().contains("it needs --all-envs or --envs"));
+
+        let args = vec!["--unknown".to_string()];
+        let err = CliOptions::parse(&args).unwrap_err();
+        assert!(err.to_string().contains("Unknown option: --unknown"));
+    }
+}
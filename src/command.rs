@@ -1,14 +1,22 @@
 use crate::{
-    dir::resolve_path,
-    env::{Env, match_env},
+    config::{ConfigDuration, one_or_many},
+    dir::{resolve_path, resolve_root},
+    env::{Env, EnvIsolation, RequiredVar, SelectedEnv, match_env},
+    fingerprint::validate_globs,
     group::{Group, GroupMode},
+    lock::{LockConfig, LockSetting},
+    matrix::Matrix,
+    process::TIMEOUT_EXIT_CODE,
     runner::Runner,
+    wait::WaitFor,
 };
 use anyhow::Result;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, de};
 use std::{
     collections::BTreeMap,
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 /// Configures when a command or group is available to run.
@@ -35,6 +43,251 @@ pub struct RootConfig {
     pub scope: RootScope,
 }
 
+/// Where a command is run from, overriding the path of the `root`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(from = "String", into = "String")]
+pub enum Cwd {
+    /// The directory ds was run from
+    Invocation,
+    /// The directory of the ds file
+    File,
+    /// The git root of the directory ds was run from
+    GitRoot,
+    /// A path, relative to the ds file, which can contain the root tokens
+    Path(PathBuf),
+}
+
+impl From<String> for Cwd {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "invocation" => Cwd::Invocation,
+            "file" => Cwd::File,
+            "git_root" => Cwd::GitRoot,
+            _ => Cwd::Path(PathBuf::from(value)),
+        }
+    }
+}
+
+impl From<Cwd> for String {
+    fn from(value: Cwd) -> Self {
+        match value {
+            Cwd::Invocation => "invocation".to_string(),
+            Cwd::File => "file".to_string(),
+            Cwd::GitRoot => "git_root".to_string(),
+            Cwd::Path(path) => path.to_string_lossy().to_string(),
+        }
+    }
+}
+
+impl Cwd {
+    /// Resolve the directory to run from, None for the invocation directory
+    fn resolve(&self, file_path: impl AsRef<Path>) -> Result<Option<PathBuf>> {
+        match self {
+            Cwd::Invocation => Ok(None),
+            Cwd::File => resolve_root("{file_dir}", file_path).map(Some),
+            Cwd::GitRoot => resolve_root("{git_root}", file_path).map(Some),
+            Cwd::Path(path) => resolve_root(path, file_path).map(Some),
+        }
+    }
+
+    /// Check if the directory is at the git root, so it can't be resolved outside of a repository
+    fn needs_git_root(&self) -> bool {
+        match self {
+            Cwd::GitRoot => true,
+            Cwd::Path(path) => path.to_string_lossy().contains("{git_root}"),
+            Cwd::Invocation | Cwd::File => false,
+        }
+    }
+}
+
+/// Retry a command when it fails, e.g. for flaky network-bound commands.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RetryConfig {
+    /// The total number of attempts, including the first one.
+    pub attempts: u32,
+    /// Optional delay before the first retry.
+    pub delay: Option<ConfigDuration>,
+    /// Optional factor to multiply the delay with after each retry, at least 1.
+    #[serde(default, deserialize_with = "deserialize_backoff")]
+    pub backoff: Option<f64>,
+    /// Optional exit codes to retry on, defaults to any non-zero exit code except timeouts.
+    pub exit_codes: Option<Vec<i32>>,
+}
+
+/// Deserialize the backoff, which can't shrink the delay, or make it infinite
+fn deserialize_backoff<'de, D>(deserializer: D) -> std::result::Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let backoff = Option::<f64>::deserialize(deserializer)?;
+
+    match backoff {
+        Some(factor) if !factor.is_finite() || factor < 1.0 => Err(de::Error::custom(format!(
+            "backoff has to be a number of at least 1, got {}",
+            factor
+        ))),
+        _ => Ok(backoff),
+    }
+}
+
+impl RetryConfig {
+    /// Check if a failed attempt with the exit code should be retried
+    /// - Timeouts are only retried if their exit code is listed
+    pub fn is_retryable(&self, exit_code: i32) -> bool {
+        match &self.exit_codes {
+            Some(codes) => codes.contains(&exit_code),
+            None => exit_code != 0 && exit_code != TIMEOUT_EXIT_CODE,
+        }
+    }
+}
+
+/// Conditions for a command or group to be available, all of them have to be met.
+///
+/// - Commands that don't meet them are hidden, like commands out of scope.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WhenConfig {
+    /// Optional files or directories that have to exist, relative to the ds file.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub exists: Option<Vec<PathBuf>>,
+    /// Optional programs that have to be found on `PATH`.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub tools: Option<Vec<String>>,
+    /// Optional environment variables that have to be set.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub vars: Option<Vec<String>>,
+    /// Optional operating systems to run on, one of them has to match (e.g. `linux`, `macos`).
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub os: Option<Vec<String>>,
+}
+
+impl WhenConfig {
+    /// Get the first condition that is not met, describing why, or None if all are met
+    pub fn unmet(&self, file_path: impl AsRef<Path>) -> Result<Option<String>> {
+        for path in self.exists.iter().flatten() {
+            if !resolve_path(path, file_path.as_ref())?.exists() {
+                return Ok(Some(format!("{} does not exist", path.display())));
+            }
+        }
+
+        for tool in self.tools.iter().flatten() {
+            if !is_on_path(tool) {
+                return Ok(Some(format!("{} is not on PATH", tool)));
+            }
+        }
+
+        for var in self.vars.iter().flatten() {
+            if std::env::var_os(var).is_none() {
+                return Ok(Some(format!("{} is not set", var)));
+            }
+        }
+
+        if let Some(os) = &self.os
+            && !os.iter().any(|os| os == std::env::consts::OS)
+        {
+            return Ok(Some(format!(
+                "requires {}, running on {}",
+                os.join(" or "),
+                std::env::consts::OS
+            )));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Check if an executable program with the name is found in one of the `PATH` directories
+fn is_on_path(program: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+
+    std::env::split_paths(&path).any(|dir| {
+        dir.join(program)
+            .metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    })
+}
+
+/// The lifecycle hooks of a command, resolved from the command and its parents
+#[derive(Debug, Default, PartialEq)]
+pub struct Hooks<'a> {
+    /// Run before the command, from the outermost group to the command itself
+    pub before: Vec<&'a str>,
+    /// Run after the command, from the command itself to the outermost group
+    pub after: Vec<&'a str>,
+    /// Run when the command failed, from the command itself to the outermost group
+    pub on_failure: Vec<&'a str>,
+}
+
+/// The shell to run command strings with.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Shell {
+    /// A shell name, e.g. `sh`, `bash`, `zsh`, `fish` or `nu`, run as `<name> -c <command>`
+    Name(String),
+    /// A custom argv, the command string is appended to it, e.g. `["python3", "-c"]`
+    Argv(Vec<String>),
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::Name("sh".to_string())
+    }
+}
+
+impl Shell {
+    /// Get the program and arguments to run a command string with
+    pub fn argv(&self) -> Vec<&str> {
+        match self {
+            Shell::Name(name) => vec![name, "-c"],
+            Shell::Argv(argv) => argv.iter().map(|arg| arg.as_str()).collect(),
+        }
+    }
+
+    /// Check if the shell takes POSIX syntax, so escaped args can be added to a command string
+    /// - Other shells, e.g. `fish`, `nu` or `["python3", "-c"]`, get args as separate arguments
+    pub fn is_posix(&self) -> bool {
+        let program = match self {
+            Shell::Name(name) => Some(name.as_str()),
+            Shell::Argv(argv) => argv.first().map(String::as_str),
+        };
+
+        program
+            .and_then(|program| Path::new(program).file_name())
+            .is_some_and(|name| {
+                matches!(
+                    name.to_str(),
+                    Some("sh" | "bash" | "zsh" | "dash" | "ksh" | "ash")
+                )
+            })
+    }
+}
+
+/// The command to run, either with a shell or directly.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CommandLine {
+    /// A command string, run with the shell.
+    Shell(String),
+    /// An argv array, run directly without a shell.
+    Exec(Vec<String>),
+}
+
+impl std::fmt::Display for CommandLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandLine::Shell(command) => write!(f, "{}", command),
+            CommandLine::Exec(argv) => {
+                let args: Vec<_> = argv
+                    .iter()
+                    .map(|arg| shell_escape::escape(arg.into()))
+                    .collect();
+                write!(f, "{}", args.join(" "))
+            }
+        }
+    }
+}
+
 /// Configuration for a single command.
 ///
 /// There is a lot of overlap with the group configuration,
@@ -45,51 +298,179 @@ pub struct CommandConfig {
     pub name: Option<String>,
     /// Optional longer description for the command, used in help messages.
     pub description: Option<String>,
-    /// The command to run.
-    pub command: String,
+    /// The command to run, a string run with the shell, or an argv array run directly.
+    /// Either this or `script` is required.
+    pub command: Option<CommandLine>,
+    /// Optional multi-line script to run instead of `command`, written to a temporary file.
+    pub script: Option<String>,
+    /// Optional program to run the script with (e.g. `python3`),
+    /// defaults to the shebang of the script, or `sh` if it has none.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub interpreter: Option<Vec<String>>,
     /// Optional environment keys (not yet implemented).
     pub envs: Option<BTreeMap<String, Env>>,
     /// Optional default environment key to use if no specific environment is set.
     pub default_env: Option<String>,
     /// Optional root configuration, to define where the command is run from.
     pub root: Option<RootConfig>,
+    /// Optionally run from another directory than the root, e.g. `invocation` or `git_root`.
+    pub cwd: Option<Cwd>,
     /// Optional aliases for the command, used to run it with different names.
     pub aliases: Option<Vec<String>>,
+    /// Optional variables that must be set before the command is run.
+    pub required_vars: Option<Vec<RequiredVar>>,
+    /// Optionally start from an empty environment, instead of inheriting the current shell.
+    pub clean_env: Option<bool>,
+    /// Optional variables to keep from the current shell, when `clean_env` is set.
+    pub inherit: Option<Vec<String>>,
+    /// Optional variables to remove from the inherited environment.
+    pub unset: Option<Vec<String>>,
+    /// Optional shell to run the command string with.
+    pub shell: Option<Shell>,
+    /// Optional time to wait for the process group to exit after SIGTERM, before it is killed.
+    pub grace_period: Option<ConfigDuration>,
+    /// Optional time after which the command is terminated.
+    pub timeout: Option<ConfigDuration>,
+    /// Optional retry configuration, to retry the command when it fails.
+    pub retry: Option<RetryConfig>,
+    /// Optional globs, relative to the root, of the files that re-run the command in watch mode.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub watch: Option<Vec<String>>,
+    /// Optionally save the output of every run of the command to a log file.
+    pub log: Option<bool>,
+    /// Optional commands to run before the command, it is skipped if one of them fails.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub before: Option<Vec<String>>,
+    /// Optional commands to run after the command, also when it failed.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub after: Option<Vec<String>>,
+    /// Optional commands to run when the command failed.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_failure: Option<Vec<String>>,
+    /// Optionally ask for confirmation before running the command.
+    pub confirm: Option<bool>,
+    /// Optional conditions for the command to be available, e.g. a file that has to exist.
+    pub when: Option<WhenConfig>,
+    /// Optionally mark the command as a service, to run it in the background with `ds start`.
+    pub service: Option<bool>,
+    /// Optionally wait for probes to succeed before the command runs, e.g. a port to be open.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub wait_for: Option<Vec<WaitFor>>,
+    /// Optional globs of the files the command reads, relative to its root.
+    /// The command is skipped when they didn't change since the last successful run.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub inputs: Option<Vec<String>>,
+    /// Optional globs of the files the command creates, relative to its root.
+    /// The command is only skipped if each of them matches a file.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub outputs: Option<Vec<String>>,
+    /// Optional values to expand the command into a copy for each of them, e.g. `["api", "web"]`.
+    /// The values are substituted as `{{item}}` (or `{{key}}` for named keys) in its name and settings.
+    #[serde(alias = "for_each")]
+    pub matrix: Option<Matrix>,
+    /// Optionally take a lock before running, so the command doesn't run more than once at the same time.
+    pub lock: Option<LockSetting>,
+}
+
+impl CommandConfig {
+    /// Get the command as shown in help messages, for scripts a summary of the script
+    pub fn display(&self) -> String {
+        match (&self.command, &self.script) {
+            (Some(command), _) => command.to_string(),
+            (None, Some(script)) => script_summary(script),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// Get what the command runs, it has either a `command` or a `script`
+    pub fn body(&self) -> Result<CommandBody<'_>> {
+        match (&self.command, &self.script) {
+            (Some(command), None) => Ok(CommandBody::Command(command)),
+            (None, Some(script)) => Ok(CommandBody::Script(script)),
+            (Some(_), Some(_)) => Err(anyhow::anyhow!(
+                "A command can't have both a `command` and a `script`"
+            )),
+            (None, None) => Err(anyhow::anyhow!("A command needs a `command` or a `script`")),
+        }
+    }
+
+    /// Check that the command has either a `command` or a `script`,
+    /// and that its `inputs` and `outputs` are inside its root
+    pub fn validate(&self) -> Result<()> {
+        self.body()?;
+        validate_globs(self.inputs.iter().chain(&self.outputs).flatten())
+    }
+}
+
+/// What a command config runs
+#[derive(Debug, Clone, Copy)]
+pub enum CommandBody<'a> {
+    /// A command, run with the shell or directly
+    Command(&'a CommandLine),
+    /// A script, run from a temporary file
+    Script(&'a str),
+}
+
+/// Summarize a script by its first line, skipping the shebang and empty lines
+pub fn script_summary(script: &str) -> String {
+    let mut lines = script
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("#!"));
+
+    let first = lines.next().unwrap_or_default();
+
+    match lines.next() {
+        Some(_) => format!("{} …", first),
+        None => first.to_string(),
+    }
 }
 
 /// A command definition in a group commands field.
+///
+/// Groups are tried before configs, as all fields of a config are optional
+/// (it can have a `command` or a `script`), while a group requires `commands`.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum Command {
     /// A simple command string.
     Inline(String),
-    /// A command with additional configuration.
-    Config(CommandConfig),
     /// A nested group of commands.
     Group(Group),
+    /// A command with additional configuration.
+    Config(CommandConfig),
 }
 
 impl Command {
-    /// Get the root path and configuration for the command
-    /// - Returns a tuple of the root configuration and the resolved path
+    /// Get the directory to run the command from
     /// - Looks at the command first, then at the parent groups
+    /// - On the same level `cwd` wins over the path of the `root`
+    /// - Returns None to run from the invocation directory
     pub fn resolve_root_path<'a>(
         &'a self,
         parents: &[&'a Group],
         file_path: impl AsRef<Path>,
     ) -> Result<Option<PathBuf>> {
-        let command_root = match self {
-            Command::Config(cmd) => cmd.root.as_ref(),
-            Command::Group(group) => group.root.as_ref(),
-            _ => None,
+        let own = match self {
+            Command::Config(cmd) => (cmd.cwd.as_ref(), cmd.root.as_ref()),
+            Command::Group(group) => (group.cwd.as_ref(), group.root.as_ref()),
+            _ => (None, None),
         };
 
-        if let Some(root) = command_root.or(parents.iter().rev().find_map(|g| g.root.as_ref())) {
-            let path = resolve_path(&root.path, file_path)?;
-            Ok(Some(path))
-        } else {
-            Ok(None)
+        let levels = parents
+            .iter()
+            .rev()
+            .map(|g| (g.cwd.as_ref(), g.root.as_ref()));
+
+        for level in [own].into_iter().chain(levels) {
+            match level {
+                (Some(cwd), _) => return cwd.resolve(file_path),
+                (None, Some(root)) => return resolve_root(&root.path, file_path).map(Some),
+                (None, None) => {}
+            }
         }
+
+        Ok(None)
     }
 
     /// Get the root configuration for the command or group,
@@ -109,13 +490,152 @@ impl Command {
         };
 
         if let Some(root) = item_root {
-            let path = resolve_path(&root.path, file_path)?;
+            let path = resolve_root(&root.path, file_path)?;
             Ok((Some(root), Some(path)))
         } else {
             Ok((None, None))
         }
     }
 
+    /// Get the first unmet `when` condition of the command or group itself, if any
+    ///
+    /// IMPORTANT!: This does not resolve the parents, see `is_in_scope`.
+    pub fn unmet_condition(&self, file_path: impl AsRef<Path>) -> Result<Option<String>> {
+        let when = match self {
+            Command::Config(cmd) => cmd.when.as_ref(),
+            Command::Group(group) => group.when.as_ref(),
+            _ => None,
+        };
+
+        match when {
+            Some(when) => when.unmet(file_path),
+            None => Ok(None),
+        }
+    }
+
+    /// Get a setting of the command, the nearest level that sets it wins
+    /// - Looks at the command first, then at the parent groups, from the innermost out
+    /// - `command` and `group` get the setting from a command config and from a group
+    fn resolve<'a, T>(
+        &'a self,
+        parents: &[&'a Group],
+        command: impl Fn(&'a CommandConfig) -> Option<T>,
+        group: impl Fn(&'a Group) -> Option<T>,
+    ) -> Option<T> {
+        let own = match self {
+            Command::Config(cmd) => command(cmd),
+            Command::Group(g) => group(g),
+            _ => None,
+        };
+
+        own.or_else(|| parents.iter().rev().find_map(|g| group(g)))
+    }
+
+    /// Get the shell to run the command with
+    pub fn resolve_shell<'a>(&'a self, parents: &[&'a Group]) -> Option<&'a Shell> {
+        self.resolve(parents, |cmd| cmd.shell.as_ref(), |g| g.shell.as_ref())
+    }
+
+    /// Get the grace period for terminating the process group
+    pub fn resolve_grace_period(&self, parents: &[&Group]) -> Option<Duration> {
+        self.resolve(parents, |cmd| cmd.grace_period, |g| g.grace_period)
+            .map(|d| d.0)
+    }
+
+    /// Get the time after which the command is terminated
+    pub fn resolve_timeout(&self, parents: &[&Group]) -> Option<Duration> {
+        self.resolve(parents, |cmd| cmd.timeout, |g| g.timeout)
+            .map(|d| d.0)
+    }
+
+    /// Get the retry configuration for the command
+    pub fn resolve_retry<'a>(&'a self, parents: &[&'a Group]) -> Option<&'a RetryConfig> {
+        self.resolve(parents, |cmd| cmd.retry.as_ref(), |g| g.retry.as_ref())
+    }
+
+    /// Get the globs of the files to watch in watch mode
+    pub fn resolve_watch<'a>(&'a self, parents: &[&'a Group]) -> Option<&'a [String]> {
+        self.resolve(parents, |cmd| cmd.watch.as_deref(), |g| g.watch.as_deref())
+    }
+
+    /// Check if the output of the command should be saved to a log file
+    pub fn resolve_log(&self, parents: &[&Group]) -> Option<bool> {
+        self.resolve(parents, |cmd| cmd.log, |g| g.log)
+    }
+
+    /// Check if running the command has to be confirmed
+    pub fn resolve_confirm(&self, parents: &[&Group]) -> bool {
+        self.resolve(parents, |cmd| cmd.confirm, |g| g.confirm)
+            .unwrap_or(false)
+    }
+
+    /// Check if the command is a service, that can be run in the background
+    pub fn resolve_service(&self, parents: &[&Group]) -> bool {
+        self.resolve(parents, |cmd| cmd.service, |g| g.service)
+            .unwrap_or(false)
+    }
+
+    /// Get the lock to take before the command runs
+    /// - `"lock": false` on a command disables the lock of its group
+    pub fn resolve_lock(&self, parents: &[&Group]) -> Option<LockConfig> {
+        self.resolve(parents, |cmd| cmd.lock.as_ref(), |g| g.lock.as_ref())
+            .and_then(LockSetting::config)
+    }
+
+    /// Get the probes to wait for before the command runs
+    pub fn wait_for(&self) -> &[WaitFor] {
+        match self {
+            Command::Config(cmd) => cmd.wait_for.as_deref().unwrap_or_default(),
+            _ => &[],
+        }
+    }
+
+    /// Get the input and output globs of the command, if it has inputs
+    pub fn inputs_outputs(&self) -> Option<(&[String], &[String])> {
+        match self {
+            Command::Config(cmd) => cmd
+                .inputs
+                .as_deref()
+                .map(|inputs| (inputs, cmd.outputs.as_deref().unwrap_or_default())),
+            _ => None,
+        }
+    }
+
+    /// Get the lifecycle hooks of the command
+    /// - Hooks of all levels are run, so a group can wrap all of its commands
+    /// - `before` hooks run outside in, `after` and `on_failure` hooks inside out
+    pub fn resolve_hooks<'a>(&'a self, parents: &[&'a Group]) -> Hooks<'a> {
+        // The hooks per level, from the outermost group to the command itself
+        let levels: Vec<[&'a Option<Vec<String>>; 3]> = parents
+            .iter()
+            .map(|g| [&g.before, &g.after, &g.on_failure])
+            .chain(match self {
+                Command::Config(cmd) => Some([&cmd.before, &cmd.after, &cmd.on_failure]),
+                Command::Group(group) => Some([&group.before, &group.after, &group.on_failure]),
+                _ => None,
+            })
+            .collect();
+
+        let collect = |index: usize, inside_out: bool| -> Vec<&'a str> {
+            let mut hooks: Vec<_> = levels.iter().map(|level| level[index]).collect();
+            if inside_out {
+                hooks.reverse();
+            }
+            hooks
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(String::as_str)
+                .collect()
+        };
+
+        Hooks {
+            before: collect(0, false),
+            after: collect(1, true),
+            on_failure: collect(2, true),
+        }
+    }
+
     /// Get the environment configuration for the command or group
     fn env(&self) -> Option<&BTreeMap<String, Env>> {
         match self {
@@ -134,19 +654,31 @@ impl Command {
         }
     }
 
+    /// Check if the environment can be matched from the first extra argument
+    /// - Only set on groups, enabled by default
+    pub fn resolve_positional_envs(&self, parents: &[&Group]) -> bool {
+        self.resolve(parents, |_| None, |g| g.positional_envs)
+            .unwrap_or(true)
+    }
+
     /// Get the command runner for the command definition
+    /// - `selected_env` is an explicitly selected environment, that takes precedence over the args
     pub fn runner<'a>(
         &'a self,
         parents: &[&'a Group],
         args: &'a [&'a str],
+        selected_env: Option<&SelectedEnv>,
         file_path: impl AsRef<Path>,
     ) -> Result<Runner> {
         let (envs, default_env) = self.resolve_envs(parents);
+        let positional = self.resolve_positional_envs(parents);
         let mut extra_args = args;
 
-        let env = if let Some((matched_env, args)) = match_env(envs, default_env, extra_args)? {
+        let env = if let Some((key, matched_env, args)) =
+            match_env(envs, default_env, selected_env, positional, extra_args)?
+        {
             extra_args = args;
-            Some(matched_env)
+            Some((key, matched_env))
         } else {
             None
         };
@@ -170,7 +702,7 @@ impl Command {
             parent.envs.as_ref()
         });
 
-        for envs in parent_envs.chain(self.env().into_iter()) {
+        for envs in parent_envs.chain(self.env()) {
             for (key, env) in envs.iter() {
                 merged.entry(key).or_insert(env);
             }
@@ -183,7 +715,36 @@ impl Command {
         (merged, default_env)
     }
 
-    /// Check if the command or group is in scope for the current directory/git root.
+    /// Get the required variables from the command and its parents
+    /// - Parent groups come first, the command itself last
+    pub fn resolve_required_vars<'a>(&'a self, parents: &[&'a Group]) -> Vec<&'a RequiredVar> {
+        let own = match self {
+            Command::Config(cmd) => cmd.required_vars.as_ref(),
+            Command::Group(group) => group.required_vars.as_ref(),
+            _ => None,
+        };
+
+        parents
+            .iter()
+            .filter_map(|g| g.required_vars.as_ref())
+            .chain(own)
+            .flatten()
+            .collect()
+    }
+
+    /// Get the environment isolation settings of the command
+    pub fn env_isolation(&self) -> EnvIsolation {
+        let mut isolation = EnvIsolation::default();
+
+        if let Command::Config(cmd) = self {
+            isolation.merge(cmd.clean_env, cmd.inherit.as_ref(), cmd.unset.as_ref());
+        }
+
+        isolation
+    }
+
+    /// Check if the command or group is in scope for the current directory/git root,
+    /// and its `when` conditions are met.
     ///
     /// IMPORTANT!: This does not resolve the parents this means it can
     /// only be used when walking the tree, and handling each group.
@@ -193,6 +754,23 @@ impl Command {
         git_root: Option<impl AsRef<Path>>,
         file_path: impl AsRef<Path>,
     ) -> Result<bool> {
+        if self.unmet_condition(file_path.as_ref())?.is_some() {
+            return Ok(false);
+        }
+
+        // A root or cwd at the git root can't be resolved outside of a git repository
+        let (cwd, root) = match self {
+            Command::Config(cmd) => (cmd.cwd.as_ref(), cmd.root.as_ref()),
+            Command::Group(group) => (group.cwd.as_ref(), group.root.as_ref()),
+            _ => (None, None),
+        };
+        if git_root.is_none()
+            && (cwd.is_some_and(Cwd::needs_git_root)
+                || root.is_some_and(|root| root.path.to_string_lossy().contains("{git_root}")))
+        {
+            return Ok(false);
+        }
+
         let root = self.own_root(file_path)?;
 
         if let (Some(root_config), Some(target_path)) = root {
@@ -301,13 +879,13 @@ impl Command {
     }
 
     /// Get the command string for the command definition
-    pub fn command(&self) -> Option<&str> {
+    pub fn command(&self) -> Option<String> {
         // Resolve a group with a default, to it's default command
         let command = self.resolve_default(&mut None);
 
         match command {
-            Command::Inline(cmd) => Some(cmd),
-            Command::Config(cmd) => Some(&cmd.command),
+            Command::Inline(cmd) => Some(cmd.clone()),
+            Command::Config(cmd) => Some(cmd.display()),
             Command::Group(_) => None,
         }
     }
@@ -315,7 +893,7 @@ impl Command {
 
 #[cfg(test)]
 mod tests {
-    use crate::ds_file::DsFile;
+    use crate::{ds_file::DsFile, env::EnvFile};
 
     use super::*;
 
@@ -411,6 +989,27 @@ mod tests {
                 git_root: Some("/command/root"),
                 is_in_scope: true,
             },
+            Case {
+                name: "Git root token, outside of a git repository",
+                command: group.commands.get("git-root-token-group").cloned().unwrap(),
+                current_dir: "/some/dir",
+                git_root: None,
+                is_in_scope: false,
+            },
+            Case {
+                name: "Git root cwd, outside of a git repository",
+                command: group.commands.get("git-root-cwd-cmd").cloned().unwrap(),
+                current_dir: "/some/dir",
+                git_root: None,
+                is_in_scope: false,
+            },
+            Case {
+                name: "Git root cwd, in a git repository",
+                command: group.commands.get("git-root-cwd-cmd").cloned().unwrap(),
+                current_dir: "/some/dir",
+                git_root: Some("/some"),
+                is_in_scope: true,
+            },
         ];
 
         for case in cases {
@@ -427,6 +1026,79 @@ mod tests {
         }
     }
 
+    #[test]
+    fn unmet_conditions() {
+        struct Case {
+            name: &'static str,
+            key: &'static str,
+            expected: Option<&'static str>,
+        }
+
+        let json = include_str!("../tests/fixtures/when.json");
+        let group: Group = serde_json::from_str(json).unwrap();
+
+        let cases = [
+            Case {
+                name: "Existing file, relative to the ds file",
+                key: "existing-file",
+                expected: None,
+            },
+            Case {
+                name: "One of the files is missing",
+                key: "missing-file",
+                expected: Some("docker-compose.yml does not exist"),
+            },
+            Case {
+                name: "Tool on PATH and variable set",
+                key: "tool",
+                expected: None,
+            },
+            Case {
+                name: "Tool not on PATH",
+                key: "missing-tool",
+                expected: Some("ds-missing-tool is not on PATH"),
+            },
+            Case {
+                name: "Variable not set",
+                key: "missing-var",
+                expected: Some("DS_MISSING_VAR is not set"),
+            },
+            Case {
+                name: "Group condition",
+                key: "docker",
+                expected: Some("ds-missing-docker is not on PATH"),
+            },
+        ];
+
+        for case in cases {
+            let command = group.commands.get(case.key).unwrap();
+            let unmet = command
+                .unmet_condition("./tests/fixtures/when.json")
+                .unwrap();
+
+            assert_eq!(unmet.as_deref(), case.expected, "{}", case.name);
+            assert_eq!(
+                command
+                    .is_in_scope("/", None::<&Path>, "./tests/fixtures/when.json")
+                    .unwrap(),
+                case.expected.is_none(),
+                "{}",
+                case.name
+            );
+        }
+
+        let other_os = group.commands.get("other-os").unwrap();
+        assert_eq!(
+            other_os
+                .unmet_condition("./tests/fixtures/when.json")
+                .unwrap(),
+            Some(format!(
+                "requires plan9 or haiku, running on {}",
+                std::env::consts::OS
+            ))
+        );
+    }
+
     #[test]
     fn resolve_root_path() {
         struct Case {
@@ -487,6 +1159,137 @@ mod tests {
         }
     }
 
+    #[test]
+    fn resolve_cwd() {
+        struct Case {
+            name: &'static str,
+            keys: Vec<&'static str>,
+            expected_root_path: Option<PathBuf>,
+        }
+
+        let json = include_str!("../tests/fixtures/cwd.json");
+        let ds_file = DsFile::from_json(json.to_string(), "/fixtures/cwd.json").unwrap();
+
+        let cases = [
+            Case {
+                name: "Invocation directory, instead of the file root",
+                keys: vec!["invocation"],
+                expected_root_path: None,
+            },
+            Case {
+                name: "Directory of the ds file",
+                keys: vec!["file"],
+                expected_root_path: Some(PathBuf::from("/fixtures")),
+            },
+            Case {
+                name: "File dir token in the root",
+                keys: vec!["file-dir-token"],
+                expected_root_path: Some(PathBuf::from("/fixtures/nested")),
+            },
+            Case {
+                name: "Explicit path",
+                keys: vec!["path"],
+                expected_root_path: Some(PathBuf::from("/some/path")),
+            },
+            Case {
+                name: "Cwd of the parent group",
+                keys: vec!["group", "inherited"],
+                expected_root_path: None,
+            },
+            Case {
+                name: "Own root wins over the cwd of the parent group",
+                keys: vec!["group", "own-root"],
+                expected_root_path: Some(PathBuf::from("/own/root")),
+            },
+        ];
+
+        for case in cases {
+            let keys: Vec<String> = case.keys.iter().map(|k| k.to_string()).collect();
+            let (command, parents) = ds_file.command_from_keys(&keys).unwrap();
+            let result = command
+                .resolve_root_path(&parents, "/fixtures/cwd.json")
+                .unwrap();
+
+            assert_eq!(result, case.expected_root_path, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn inherit_file_settings() {
+        struct Case {
+            name: &'static str,
+            keys: Vec<&'static str>,
+            expected_root_path: Option<PathBuf>,
+            expected_shell: Option<Shell>,
+            expected_grace_period: Option<Duration>,
+            expected_timeout: Option<Duration>,
+        }
+
+        let json = include_str!("../tests/fixtures/file-settings.json");
+        let ds_file = DsFile::from_json(json.to_string(), "/fixtures/file-settings.json").unwrap();
+
+        let cases = [
+            Case {
+                name: "Top level command, uses the file settings",
+                keys: vec!["build"],
+                expected_root_path: Some(PathBuf::from("/file/root")),
+                expected_shell: Some(Shell::Name("bash".to_string())),
+                expected_grace_period: Some(Duration::from_secs(10)),
+                expected_timeout: Some(Duration::from_secs(60)),
+            },
+            Case {
+                name: "Group settings win over the file settings",
+                keys: vec!["web", "dev"],
+                expected_root_path: Some(PathBuf::from("/web")),
+                expected_shell: Some(Shell::Name("zsh".to_string())),
+                expected_grace_period: Some(Duration::from_secs(10)),
+                expected_timeout: Some(Duration::from_secs(60)),
+            },
+            Case {
+                name: "Command settings win over the group and file settings",
+                keys: vec!["web", "test"],
+                expected_root_path: Some(PathBuf::from("/web")),
+                expected_shell: Some(Shell::Name("zsh".to_string())),
+                expected_grace_period: Some(Duration::from_secs(10)),
+                expected_timeout: Some(Duration::from_secs(300)),
+            },
+        ];
+
+        for case in cases {
+            let keys: Vec<String> = case.keys.iter().map(|k| k.to_string()).collect();
+            let (command, parents) = ds_file.command_from_keys(&keys).unwrap();
+
+            // The file is always the outermost parent
+            assert!(std::ptr::eq(parents[0], &ds_file.group), "{}", case.name);
+            assert_eq!(
+                command
+                    .resolve_root_path(&parents, "/fixtures/file-settings.json")
+                    .unwrap(),
+                case.expected_root_path,
+                "{}",
+                case.name
+            );
+            assert_eq!(
+                command.resolve_shell(&parents),
+                case.expected_shell.as_ref(),
+                "{}",
+                case.name
+            );
+            assert_eq!(
+                command.resolve_grace_period(&parents),
+                case.expected_grace_period,
+                "{}",
+                case.name
+            );
+            assert_eq!(
+                command.resolve_timeout(&parents),
+                case.expected_timeout,
+                "{}",
+                case.name
+            );
+        }
+    }
+
     #[test]
     fn aliases() {
         let file = include_str!("../tests/fixtures/aliases-and-group-mode.json");
@@ -510,18 +1313,14 @@ mod tests {
             ds_file: &DsFile,
             expected_aliases: Vec<Vec<&'static str>>,
         ) -> Case {
+            // The parents include the root (file) group
             let (command, parents) = ds_file.command_from_keys(keys).unwrap();
 
-            let parents_with_root = vec![ds_file.group.clone()]
-                .into_iter()
-                .chain(parents.into_iter().cloned().collect::<Vec<Group>>())
-                .collect::<Vec<Group>>();
-
             Case {
                 name,
                 command: command.clone(),
                 keys: keys.to_vec(),
-                parents: parents_with_root,
+                parents: parents.into_iter().cloned().collect(),
                 expected_aliases,
             }
         }
@@ -629,25 +1428,23 @@ mod tests {
                     (
                         "group-config",
                         Env::Config(crate::env::EnvConfig {
-                            path: Some(".group-env".to_string()),
+                            path: Some(vec![EnvFile::Path(".group-env".to_string())]),
                             vars: Some(vars_map(&[("GROUP_VAR", "group_value")])),
-                            command_prefix: None,
+                            ..Default::default()
                         }),
                     ),
                     (
                         "group-vars",
                         Env::Config(crate::env::EnvConfig {
-                            path: None,
                             vars: Some(vars_map(&[("GROUP_VARS", "group_vars")])),
-                            command_prefix: None,
+                            ..Default::default()
                         }),
                     ),
                     (
                         "group-command",
                         Env::Config(crate::env::EnvConfig {
-                            path: None,
                             command_prefix: Some("echo 'GroupEnvCommand'".to_string()),
-                            vars: None,
+                            ..Default::default()
                         }),
                     ),
                 ],
@@ -669,33 +1466,30 @@ mod tests {
                     (
                         "group-config",
                         Env::Config(crate::env::EnvConfig {
-                            path: Some(".group-env".to_string()),
+                            path: Some(vec![EnvFile::Path(".group-env".to_string())]),
                             vars: Some(vars_map(&[("GROUP_VAR", "group_value")])),
-                            command_prefix: None,
+                            ..Default::default()
                         }),
                     ),
                     (
                         "group-vars",
                         Env::Config(crate::env::EnvConfig {
-                            path: None,
                             vars: Some(vars_map(&[("GROUP_VARS", "group_vars")])),
-                            command_prefix: None,
+                            ..Default::default()
                         }),
                     ),
                     (
                         "group-command",
                         Env::Config(crate::env::EnvConfig {
-                            path: None,
-                            vars: None,
                             command_prefix: Some("echo 'GroupEnvCommand'".to_string()),
+                            ..Default::default()
                         }),
                     ),
                     (
                         "command-vars",
                         Env::Config(crate::env::EnvConfig {
-                            path: None,
                             vars: Some(vars_map(&[("COMMAND_VAR", "command_value")])),
-                            command_prefix: None,
+                            ..Default::default()
                         }),
                     ),
                     ("command-dotenv", Env::Dotenv(".command-env".to_string())),
@@ -704,14 +1498,9 @@ mod tests {
         ];
 
         for case in cases {
+            // The parents start with the file itself
             let (command, parents) = ds_file.command_from_keys(&case.keys).unwrap();
-            let parents_with_root = vec![ds_file.group.clone()]
-                .into_iter()
-                .chain(parents.into_iter().cloned().collect::<Vec<Group>>())
-                .collect::<Vec<Group>>();
-
-            let (envs, default_env) =
-                command.resolve_envs(&parents_with_root.iter().collect::<Vec<&Group>>());
+            let (envs, default_env) = command.resolve_envs(&parents);
 
             let mut got_env_keys = envs.keys().map(|k| k.to_string()).collect::<Vec<String>>();
             let mut expected_env_keys = case
@@ -735,4 +1524,71 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn resolve_hooks() {
+        let file = include_str!("../tests/fixtures/hooks.json");
+        let ds_file = DsFile::from_json(file.to_string(), "../tests/fixtures/hooks.json").unwrap();
+
+        struct Case {
+            name: &'static str,
+            keys: Vec<&'static str>,
+            expected: Hooks<'static>,
+        }
+
+        let cases = [
+            Case {
+                name: "Command without groups, only file hooks",
+                keys: vec!["lint"],
+                expected: Hooks {
+                    before: vec!["echo 'file before'"],
+                    after: vec!["echo 'file after'"],
+                    on_failure: vec![],
+                },
+            },
+            Case {
+                name: "Inline command, inherits group hooks",
+                keys: vec!["api", "test"],
+                expected: Hooks {
+                    before: vec![
+                        "echo 'file before'",
+                        "echo 'api before 1'",
+                        "echo 'api before 2'",
+                    ],
+                    after: vec!["echo 'api after'", "echo 'file after'"],
+                    on_failure: vec!["echo 'api failed'"],
+                },
+            },
+            Case {
+                name: "Command hooks, wrapped by the group hooks",
+                keys: vec!["api", "deploy"],
+                expected: Hooks {
+                    before: vec![
+                        "echo 'file before'",
+                        "echo 'api before 1'",
+                        "echo 'api before 2'",
+                        "echo 'deploy before'",
+                    ],
+                    after: vec![
+                        "echo 'deploy after'",
+                        "echo 'api after'",
+                        "echo 'file after'",
+                    ],
+                    on_failure: vec!["echo 'api failed'"],
+                },
+            },
+        ];
+
+        for case in cases {
+            let keys: Vec<String> = case.keys.iter().map(|k| k.to_string()).collect();
+            let (command, parents) = ds_file.command_from_keys(&keys).unwrap();
+
+            assert_eq!(
+                command.resolve_hooks(&parents),
+                case.expected,
+                "{}",
+                case.name
+            );
+        }
+    }
 }
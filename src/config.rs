@@ -1,8 +1,8 @@
 use crate::dir::git_root;
 use anyhow::Result;
 use glob::glob;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, env, path::PathBuf};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashSet, env, path::PathBuf, time::Duration};
 
 /// Configure how to handle commands with the same key
 #[derive(Debug, Serialize, Deserialize, Default, Copy, Clone)]
@@ -24,6 +24,84 @@ pub struct GlobalConfig {
     pub on_conflict: OnConflict,
     /// Optional list of files to to collect commands from
     pub ds_files: Option<Vec<String>>,
+    /// How long to wait for the process group to exit after SIGTERM, before it is killed
+    pub grace_period: Option<ConfigDuration>,
+    /// Save the output of every run to a log file, can be overridden per command
+    pub log: Option<bool>,
+    /// How many log files to keep per command, defaults to 10
+    pub max_logs: Option<usize>,
+    /// Print a summary line with the exit status and wall time after a command finished
+    pub summary: Option<bool>,
+    /// Send a terminal notification when a command ran longer than this
+    pub notify_after: Option<ConfigDuration>,
+}
+
+/// Deserialize a single value or a list of values into a list
+pub fn one_or_many<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    let value = Option::<OneOrMany<T>>::deserialize(deserializer)?;
+
+    Ok(value.map(|value| match value {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    }))
+}
+
+/// A duration in the config, either a number of seconds, or a string like `1m 30s`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigDuration(pub Duration);
+
+impl Serialize for ConfigDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&humantime::format_duration(self.0).to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ConfigDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Seconds(f64),
+            Text(String),
+        }
+
+        match Value::deserialize(deserializer)? {
+            Value::Seconds(seconds) => Duration::try_from_secs_f64(seconds)
+                .map(ConfigDuration)
+                .map_err(serde::de::Error::custom),
+            Value::Text(text) => humantime::parse_duration(&text)
+                .map(ConfigDuration)
+                .map_err(|e| {
+                    serde::de::Error::custom(format!("Invalid duration '{}': {}", text, e))
+                }),
+        }
+    }
+}
+
+impl std::str::FromStr for ConfigDuration {
+    type Err = anyhow::Error;
+
+    /// Parse a number of seconds, or a string like `1m 30s`
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(seconds) = s.parse::<f64>() {
+            return Ok(ConfigDuration(Duration::try_from_secs_f64(seconds)?));
+        }
+
+        humantime::parse_duration(s)
+            .map(ConfigDuration)
+            .map_err(|e| anyhow::anyhow!("Invalid duration '{}': {}", s, e))
+    }
 }
 
 /// Get the configuration directory path, typically ~/.config/dosomething
@@ -31,11 +109,26 @@ pub fn get_config_dir() -> Option<std::path::PathBuf> {
     env::home_dir().map(|f| f.join(".config").join("do-something"))
 }
 
+/// Get the state directory path, typically ~/.local/state/do-something
+/// - Used for data that ds keeps between runs, like logs
+pub fn get_state_dir() -> Option<std::path::PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|f| f.join(".local").join("state")))
+        .map(|dir| dir.join("do-something"))
+}
+
 impl Default for GlobalConfig {
     fn default() -> Self {
         GlobalConfig {
             on_conflict: OnConflict::Error,
             ds_files: None,
+            grace_period: None,
+            log: None,
+            max_logs: None,
+            summary: None,
+            notify_after: None,
         }
     }
 }
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use crossterm::style::Stylize;
+use std::{
+    io::{self, IsTerminal, Write},
+    path::Path,
+};
+
+/// What is about to be run, shown when asking for confirmation
+pub struct Confirmation<'a> {
+    pub command: &'a str,
+    pub env: Option<&'a str>,
+    pub root: Option<&'a Path>,
+    /// The answer to type to continue, `yes` or the name of the env
+    pub answer: &'a str,
+}
+
+impl Confirmation<'_> {
+    /// Ask for confirmation on the terminal, returns an error if it is not confirmed
+    /// - Refuses automatically if stdin is not a terminal, as nobody can answer
+    pub fn ask(&self) -> Result<()> {
+        if !io::stdin().is_terminal() {
+            return Err(anyhow::anyhow!(
+                "Refusing to run without confirmation, stdin is not a terminal (use --yes to skip it): {}",
+                self.command
+            ));
+        }
+
+        eprintln!("{}", "This command has to be confirmed:".yellow());
+        eprintln!("  command: {}", self.command);
+        if let Some(env) = self.env {
+            eprintln!("  env:     {}", env.bold());
+        }
+        if let Some(root) = self.root {
+            eprintln!("  root:    {}", root.display());
+        }
+
+        eprint!("Type '{}' to continue: ", self.answer);
+        io::stderr().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        self.check(&input)
+    }
+
+    /// Check the typed answer
+    fn check(&self, input: &str) -> Result<()> {
+        if input.trim() == self.answer {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Aborted, the command was not confirmed"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_answer() {
+        struct Case {
+            name: &'static str,
+            answer: &'static str,
+            input: &'static str,
+            expected: bool,
+        }
+
+        let cases = [
+            Case {
+                name: "Yes",
+                answer: "yes",
+                input: "yes\n",
+                expected: true,
+            },
+            Case {
+                name: "Whitespace around the answer",
+                answer: "yes",
+                input: "  yes \n",
+                expected: true,
+            },
+            Case {
+                name: "Short answer is not enough",
+                answer: "yes",
+                input: "y\n",
+                expected: false,
+            },
+            Case {
+                name: "Env name",
+                answer: "prod",
+                input: "prod\n",
+                expected: true,
+            },
+            Case {
+                name: "Yes is not enough for an env",
+                answer: "prod",
+                input: "yes\n",
+                expected: false,
+            },
+            Case {
+                name: "Empty input, e.g. EOF",
+                answer: "yes",
+                input: "",
+                expected: false,
+            },
+        ];
+
+        for case in cases {
+            let confirmation = Confirmation {
+                command: "echo 'Deploying'",
+                env: None,
+                root: None,
+                answer: case.answer,
+            };
+
+            assert_eq!(
+                confirmation.check(case.input).is_ok(),
+                case.expected,
+                "{}",
+                case.name
+            );
+        }
+    }
+}
@@ -2,6 +2,7 @@ use anyhow::Result;
 use git2::Repository;
 
 use std::env;
+use std::hash::Hasher;
 use std::path::{self, Path, PathBuf};
 
 /// Find the current Git root directory
@@ -40,6 +41,37 @@ pub fn resolve_path(input: impl AsRef<Path>, file_path: impl AsRef<Path>) -> Res
     }
 }
 
+/// Resolve a root path like `resolve_path`, first replacing the tokens in it
+/// - `{file_dir}`: The directory of the ds file
+/// - `{git_root}`: The git root of the current directory
+pub fn resolve_root(input: impl AsRef<Path>, file_path: impl AsRef<Path>) -> Result<PathBuf> {
+    let input = input.as_ref().to_string_lossy();
+    let mut expanded = input.to_string();
+
+    if expanded.contains("{file_dir}") {
+        let file_dir = resolve_path(".", &file_path)?;
+        expanded = expanded.replace(
+            "{file_dir}",
+            file_dir.to_string_lossy().trim_end_matches('/'),
+        );
+    }
+
+    if expanded.contains("{git_root}") {
+        let git_root = git_root().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Not in a git repository, needed for {{git_root}} in: {}",
+                input
+            )
+        })?;
+        expanded = expanded.replace(
+            "{git_root}",
+            git_root.to_string_lossy().trim_end_matches('/'),
+        );
+    }
+
+    resolve_path(expanded, file_path)
+}
+
 /// Collapse a path to use `~` for the home directory if applicable
 pub fn collapse_to_tilde(path: &Path) -> String {
     if let Some(home) = env::home_dir()
@@ -50,3 +82,154 @@ pub fn collapse_to_tilde(path: &Path) -> String {
     }
     path.display().to_string()
 }
+
+/// FNV-1a hasher, unlike the default hasher its hashes stay the same between ds versions
+/// - Used for ids and fingerprints that are stored in the state directory
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Get a stable, file system safe id for a path, e.g. to store state per ds file
+/// - Uses the file name for readability, and a hash of the full path to keep it unique
+pub fn path_id(path: &Path) -> String {
+    let mut hasher = Fnv::default();
+    hasher.write(path.as_os_str().as_encoded_bytes());
+    let hash = hasher.finish();
+
+    let name: String = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{}-{:016x}", name, hash)
+}
+
+/// Encode a name into a single file name, that can't escape its directory (e.g. `../x` or `a/b`)
+/// - Letters, digits, `-`, `_` and `.` stay readable, other bytes are percent-encoded
+/// - A leading `.` is encoded too, so the name is never `.`, `..` or hidden
+pub fn file_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+
+    for (i, byte) in name.bytes().enumerate() {
+        match byte {
+            b'.' if i == 0 => encoded.push_str("%2E"),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+/// Decode a file name created with `file_name`
+pub fn decode_file_name(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| {
+            std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        });
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Get the file name to store state of a command by its keys, e.g. `api dev` for `["api", "dev"]`
+pub fn keys_file_name(keys: &[String]) -> String {
+    keys.iter()
+        .map(|key| file_name(key))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Get the keys of a command back from a file name created with `keys_file_name`
+pub fn keys_from_file_name(name: &str) -> Vec<String> {
+    name.split(' ').map(decode_file_name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_file_names() {
+        struct Case {
+            name: &'static str,
+            keys: Vec<&'static str>,
+            expected: &'static str,
+        }
+
+        let cases = [
+            Case {
+                name: "Plain keys stay readable",
+                keys: vec!["api", "dev-server", "v1.2"],
+                expected: "api dev-server v1.2",
+            },
+            Case {
+                name: "Separators are encoded",
+                keys: vec!["../etc", "a/b"],
+                expected: "%2E.%2Fetc a%2Fb",
+            },
+            Case {
+                name: "Dot keys are encoded",
+                keys: vec![".", ".."],
+                expected: "%2E %2E.",
+            },
+            Case {
+                name: "Spaces and percent signs in keys",
+                keys: vec!["two words", "100%"],
+                expected: "two%20words 100%25",
+            },
+        ];
+
+        for case in cases {
+            let keys: Vec<String> = case.keys.iter().map(|key| key.to_string()).collect();
+            let name = keys_file_name(&keys);
+
+            assert_eq!(name, case.expected, "{}", case.name);
+            assert_eq!(keys_from_file_name(&name), keys, "{}", case.name);
+        }
+    }
+}
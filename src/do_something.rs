@@ -1,17 +1,116 @@
 use crate::{
+    cli::{BUILTIN_COMMANDS, CliOptions},
     command::Command,
     config::{GlobalConfig, OnConflict},
-    dir::git_root,
+    confirm::Confirmation,
+    dir::{collapse_to_tilde, git_root},
     ds_file::{DsFile, Match},
+    env::{ExportFormat, SelectedEnv, export_vars, match_env},
+    fingerprint::{Fingerprints, UpToDate},
     group::Group,
     help::{HelpGroup, HelpRow},
-    runner::Runner,
+    history::{History, HistoryEntry, print_history},
+    labelled::run_labelled,
+    logs::{DEFAULT_MAX_LOGS, RunLog, print_log, run_logged},
+    process::{DEFAULT_GRACE_PERIOD, ProcessOptions, TIMEOUT_EXIT_CODE, exit_with},
+    runner::{RunOptions, Runner, run_hooks},
+    service::{Services, print_status},
+    summary::{RunRow, notify_finished, print_results, print_summary},
     tui::run_tui,
+    wait::{ReadyCheck, parse_probes, sh_command, wait_for},
+    watch::{WatchFilter, watch},
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
 use crossterm::style::Stylize;
 use std::io::IsTerminal;
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command as ProcessCommand,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// The outcome of a command that ran to completion
+struct Finished {
+    exit_code: i32,
+    /// If the command was interrupted, e.g. with Ctrl+C
+    interrupted: bool,
+    elapsed: Duration,
+}
+
+impl Finished {
+    /// A command that couldn't run, e.g. a refused confirmation or a busy lock, counts as failed
+    /// - The error is printed, so the other commands can still run
+    fn failed(error: anyhow::Error) -> Self {
+        eprintln!("{}", format!("{:#}", error).red());
+        Finished {
+            exit_code: 1,
+            interrupted: false,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+/// Fill in the outcome of an env that ran, keeping the order of the envs
+fn set_finished(results: &mut [(String, Option<Finished>)], env: &str, finished: Finished) {
+    if let Some((_, result)) = results.iter_mut().find(|(name, _)| name == env) {
+        *result = Some(finished);
+    }
+}
+
+/// A command that is ready to run, with its command string and options
+type PreparedRun = (String, Box<ProcessCommand>, Box<RunOptions>);
+
+/// Exit with `124` like a timed out command, if waiting for a probe timed out
+fn exit_on_timeout(timed_out: Option<String>) -> Result<()> {
+    if let Some(timed_out) = timed_out {
+        eprintln!("{}", timed_out.red());
+        exit_with(TIMEOUT_EXIT_CODE);
+    }
+
+    Ok(())
+}
+
+/// Whether a command with inputs and outputs has to run
+enum Freshness {
+    /// The command has no inputs and outputs, or runs in watch mode
+    Unknown,
+    /// The inputs didn't change since the last successful run and the outputs exist
+    UpToDate,
+    /// The fingerprint to save once the command succeeds
+    Changed((Fingerprints, String)),
+}
+
+fn up_to_date_message(cmd_str: &str) -> String {
+    format!(
+        "Up to date, skipping: {} (use --force to run it anyway)",
+        cmd_str
+    )
+}
+
+fn timed_out_message(timeout: Duration, cmd_str: &str) -> String {
+    format!(
+        "Command timed out after {}: {}",
+        humantime::format_duration(timeout),
+        cmd_str
+    )
+}
+
+/// Save the fingerprint of a successful run
+/// - Failing to save it only means the command runs again next time, so it only warns
+fn save_fingerprint(
+    (fingerprints, fingerprint): (Fingerprints, String),
+    keys: &[String],
+    options: &RunOptions,
+) {
+    if let Err(e) = fingerprints.set(keys, options.env.as_deref(), &fingerprint) {
+        eprintln!(
+            "{}",
+            format!("Failed to save fingerprint: {:#}", e).yellow()
+        );
+    }
+}
 
 /// Collection of loaded ds_files, to avoid reloading them multiple times
 #[derive(Default)]
@@ -44,11 +143,12 @@ pub struct DoSomething {
     pub paths: Vec<PathBuf>,
     pub current_dir: PathBuf,
     pub git_root: Option<PathBuf>,
+    pub options: CliOptions,
 }
 
 impl DoSomething {
     /// Create a new DoSomething instance, loading configuration and file paths
-    pub fn new() -> Result<Self> {
+    pub fn new(options: CliOptions) -> Result<Self> {
         let config = GlobalConfig::load()?;
         let paths = config.file_paths()?;
 
@@ -58,6 +158,7 @@ impl DoSomething {
             paths,
             current_dir: std::env::current_dir()?,
             git_root: git_root(),
+            options,
         })
     }
 
@@ -87,11 +188,35 @@ impl DoSomething {
 
         // Return the first match if any
         match matches.into_iter().next() {
-            None => Err(anyhow::anyhow!("No matching command found")),
+            None => Err(self.no_match_error(target)),
             Some(m) => Ok(m),
         }
     }
 
+    /// The error when no command matches, explaining which commands are hidden by their conditions
+    fn no_match_error(&mut self, target: &[&str]) -> anyhow::Error {
+        let mut hidden = Vec::new();
+
+        for path in &self.paths {
+            if let Ok(file) = self.ds_files.load_file(path)
+                && let Ok(matches) = file.hidden_matches(target)
+            {
+                hidden.extend(matches);
+            }
+        }
+
+        if hidden.is_empty() {
+            return anyhow::anyhow!("No matching command found");
+        }
+
+        let reasons = hidden
+            .iter()
+            .map(|(m, reason)| format!("\n  '{}' is not available: {}", m.keys.join(" "), reason))
+            .collect::<String>();
+
+        anyhow::anyhow!("No matching command found{}", reasons)
+    }
+
     /// Get the command and its parents from a match
     pub fn command_from_match(&mut self, match_: &Match) -> Result<(&Command, Vec<&Group>)> {
         let file = self.ds_files.load_file(&match_.file_path)?;
@@ -144,29 +269,1023 @@ impl DoSomething {
 
     /// Run the command, if it is a command runner
     /// - If it is a help runner, it does nothing
-    pub fn run(&self, runner: Runner) -> Result<()> {
-        if let Runner::Command(cmd_str, mut command) = runner {
+    /// - Exits with the exit code of the command, or by the signal if it was interrupted
+    /// - Exits with `124` if the command timed out
+    /// - With `--watch`, it re-runs the command when the watched files change
+    /// - Asks for confirmation first if required, unless `--yes` is passed
+    /// - Runs the `before`, `after` and `on_failure` hooks around it, without changing the exit code
+    /// - Optionally prints a summary, and sends a notification for long running commands
+    pub fn run(&self, runner: Runner, file_path: &Path, keys: &[String]) -> Result<()> {
+        if let Runner::Command(cmd_str, command, options) = runner {
+            let finished = self.execute(&cmd_str, command, *options, file_path, keys)?;
+
+            if self.config.summary.unwrap_or(false) {
+                print_summary(&cmd_str, finished.exit_code, finished.elapsed);
+            }
+
+            if let Some(threshold) = self.config.notify_after
+                && finished.elapsed >= threshold.0
+            {
+                notify_finished(&cmd_str, finished.exit_code, finished.elapsed);
+            }
+
+            exit_with(finished.exit_code);
+        }
+
+        Ok(())
+    }
+
+    /// Run the command with its hooks and record it in the history, without exiting
+    /// - Takes the options by value, so temporary files (e.g. scripts) are removed when done,
+    ///   as exiting skips destructors
+    fn execute(
+        &self,
+        cmd_str: &str,
+        mut command: Box<ProcessCommand>,
+        mut options: RunOptions,
+        file_path: &Path,
+        keys: &[String],
+    ) -> Result<Finished> {
+        // Skip the command if its inputs didn't change since the last successful run
+        let fingerprint = match self.check_inputs(cmd_str, &command, &options, file_path, keys)? {
+            Freshness::UpToDate => {
+                println!("{}", up_to_date_message(cmd_str).dim());
+                return Ok(Finished {
+                    exit_code: 0,
+                    interrupted: false,
+                    elapsed: Duration::ZERO,
+                });
+            }
+            Freshness::Changed(fingerprint) => Some(fingerprint),
+            Freshness::Unknown => None,
+        };
+
+        self.confirm(cmd_str, &command, &options)?;
+        options.write_script()?;
+
+        // Hold the lock until the command and its hooks are done
+        let _lock = options
+            .lock
+            .as_ref()
+            .map(|lock| lock.acquire(file_path, keys))
+            .transpose()?;
+
+        let log = match options.log.or(self.config.log).unwrap_or(false) {
+            true => Some(self.run_log(file_path, keys)?),
+            false => None,
+        };
+
+        let process_options = self.process_options(&options);
+
+        // The timeout only applies to the command itself
+        let hook_options = ProcessOptions {
+            timeout: None,
+            ..process_options
+        };
+
+        let started = SystemTime::now();
+
+        // The command is skipped if one of the before hooks fails
+        let mut before = run_hooks("before", &mut options.hooks.before, hook_options);
+
+        // Wait for the probes after the before hooks, as those might start what is waited for
+        // Only a probe that isn't ready in time fails like a timeout
+        if before == 0 {
+            match wait_for(&mut options.wait_for, file_path) {
+                Ok(None) => {}
+                Ok(Some(timed_out)) => {
+                    eprintln!("{}", timed_out.red());
+                    before = TIMEOUT_EXIT_CODE;
+                }
+                Err(e) => {
+                    eprintln!("{}", format!("{:#}", e).red());
+                    before = 1;
+                }
+            }
+        }
+
+        let (exit_code, interrupted) = if before != 0 {
+            (before, false)
+        } else if self.options.watch {
+            let root = command.get_current_dir().unwrap_or(&self.current_dir);
+            let filter =
+                WatchFilter::new(root, options.watch.as_deref(), self.git_root.as_deref())?;
+            let exit_code = watch(
+                command,
+                cmd_str,
+                process_options,
+                options.retry.clone(),
+                log,
+                filter,
+            )?;
+
+            // Watching only stops when interrupted
+            (exit_code, true)
+        } else {
             println!("{}", cmd_str.dim());
-            let status = command.spawn()?.wait()?;
-            std::process::exit(status.code().unwrap_or(1));
+
+            let outcome = run_logged(
+                &mut command,
+                process_options,
+                options.retry.as_ref(),
+                log.as_ref(),
+            )?;
+
+            if outcome.timed_out
+                && let Some(timeout) = process_options.timeout
+            {
+                eprintln!("{}", timed_out_message(timeout, cmd_str).red());
+            }
+
+            (outcome.exit_code(), outcome.interrupted)
+        };
+
+        if exit_code != 0 && !interrupted {
+            run_hooks("on_failure", &mut options.hooks.on_failure, hook_options);
+        }
+        run_hooks("after", &mut options.hooks.after, hook_options);
+
+        self.record_history(started, file_path, keys, &options, exit_code);
+
+        if exit_code == 0
+            && let Some(fingerprint) = fingerprint
+        {
+            save_fingerprint(fingerprint, keys, &options);
+        }
+
+        Ok(Finished {
+            exit_code,
+            interrupted,
+            elapsed: started.elapsed().unwrap_or_default(),
+        })
+    }
+
+    /// Compare the fingerprint of the inputs of the command to its last successful run
+    /// - Not checked in watch mode, as the command re-runs on changes anyway
+    fn check_inputs(
+        &self,
+        cmd_str: &str,
+        command: &ProcessCommand,
+        options: &RunOptions,
+        file_path: &Path,
+        keys: &[String],
+    ) -> Result<Freshness> {
+        let Some((inputs, outputs)) = &options.inputs_outputs else {
+            return Ok(Freshness::Unknown);
+        };
+        if self.options.watch {
+            return Ok(Freshness::Unknown);
+        }
+
+        let up_to_date = UpToDate::new(
+            command.get_current_dir().unwrap_or(&self.current_dir),
+            inputs,
+            outputs,
+        );
+        let fingerprint = up_to_date.fingerprint(cmd_str, command)?;
+        let fingerprints = Fingerprints::new(file_path)?;
+
+        if !self.options.force
+            && fingerprints.get(keys, options.env.as_deref()).as_deref()
+                == Some(fingerprint.as_str())
+            && up_to_date.outputs_exist()?
+        {
+            return Ok(Freshness::UpToDate);
+        }
+
+        Ok(Freshness::Changed((fingerprints, fingerprint)))
+    }
+
+    /// Get how to run the command, the `--timeout` option wins over the configured timeout
+    fn process_options(&self, options: &RunOptions) -> ProcessOptions {
+        ProcessOptions {
+            grace_period: options
+                .grace_period
+                .or(self.config.grace_period.map(|d| d.0))
+                .unwrap_or(DEFAULT_GRACE_PERIOD),
+            timeout: self.options.timeout.map(|d| d.0).or(options.timeout),
+            background: false,
+        }
+    }
+
+    /// Ask for confirmation if the command has to be confirmed, unless `--yes` was passed
+    fn confirm(&self, cmd_str: &str, command: &ProcessCommand, options: &RunOptions) -> Result<()> {
+        if let Some(answer) = &options.confirm
+            && !self.options.yes
+        {
+            let confirmation = Confirmation {
+                command: cmd_str,
+                env: options.env.as_deref(),
+                root: command.get_current_dir(),
+                answer,
+            };
+            confirmation.ask()?;
         }
 
         Ok(())
     }
 
+    /// Add the run to the history
+    /// - Failing to record it should not change the outcome of the command, so it only warns
+    fn record_history(
+        &self,
+        started: SystemTime,
+        file_path: &Path,
+        keys: &[String],
+        options: &RunOptions,
+        exit_code: i32,
+    ) {
+        let entry = HistoryEntry::new(
+            started,
+            &self.current_dir,
+            file_path,
+            keys,
+            options.env.as_deref(),
+            &options.args,
+            exit_code,
+        );
+
+        if let Err(e) = History::open().and_then(|history| history.record(entry)) {
+            eprintln!("{}", format!("Failed to record history: {:#}", e).yellow());
+        }
+    }
+
+    /// Get where to save the logs of a command
+    fn run_log(&self, file_path: &Path, keys: &[String]) -> Result<RunLog> {
+        let max_logs = self.config.max_logs.unwrap_or(DEFAULT_MAX_LOGS);
+        RunLog::new(file_path, keys, max_logs)
+    }
+
+    /// Print the latest log of the matching command, `ds logs [--follow] <keys...>`
+    pub fn print_logs(&mut self, args: &[&str]) -> Result<()> {
+        let follow = args.iter().any(|arg| *arg == "-f" || *arg == "--follow");
+        let keys: Vec<&str> = args
+            .iter()
+            .copied()
+            .filter(|arg| *arg != "-f" && *arg != "--follow")
+            .collect();
+
+        if keys.is_empty() {
+            return Err(anyhow::anyhow!("Usage: ds logs [--follow] <keys...>"));
+        }
+
+        let match_ = self.match_command(&keys)?;
+        let log = self.run_log(&match_.file_path, &match_.keys)?;
+        print_log(&log, follow)
+    }
+
+    /// Print the run history, `ds history [--json] [--limit <n>] [filter...]`
+    pub fn print_history(&self, args: &[&str]) -> Result<()> {
+        let mut json = false;
+        let mut limit = 20;
+        let mut filter = Vec::new();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match *arg {
+                "--json" => json = true,
+                "--limit" | "-n" => {
+                    limit = iter
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Missing value for option '{}'", arg))?
+                        .parse()?
+                }
+                _ => filter.push(*arg),
+            }
+        }
+
+        let entries = History::open()?.load()?;
+        print_history(&entries, &filter, limit, json)
+    }
+
+    /// Re-run an entry from the history, `ds again [id] [-- args...]`
+    /// - Runs the same command from the same ds file, env and directory, without matching it again
+    /// - Args after `--` replace the extra args of the entry
+    pub fn run_again(&mut self, args: &[&str]) -> Result<()> {
+        let (args, new_args) = match args.iter().position(|arg| *arg == "--") {
+            Some(index) => (&args[..index], Some(&args[index + 1..])),
+            None => (args, None),
+        };
+
+        let id = match args {
+            [] => 1,
+            [id] => id
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid history entry: {}", id))?,
+            _ => return Err(anyhow::anyhow!("Usage: ds again [id] [-- args...]")),
+        };
+
+        let entry = History::open()?.get(id)?;
+
+        // Resolve the command like it was run from the recorded directory, e.g. its git root
+        if entry.cwd.is_dir() {
+            std::env::set_current_dir(&entry.cwd)?;
+            self.current_dir = entry.cwd.clone();
+            self.git_root = git_root();
+        }
+
+        let extra_args: Vec<&str> = match new_args {
+            Some(args) => args.to_vec(),
+            None => entry.args.iter().map(String::as_str).collect(),
+        };
+
+        let file = self.ds_files.load_file(&entry.file_path)?;
+        let (command, parents) = file.command_from_keys(&entry.keys)?;
+        let env = entry.env.clone().map(SelectedEnv::Flag);
+        let runner = command.runner(&parents, &extra_args, env.as_ref(), &entry.file_path)?;
+
+        self.run(runner, &entry.file_path, &entry.keys)
+    }
+
+    /// Get the matching command for a service action, checking that it is a service
+    fn match_service<'a>(
+        &mut self,
+        action: &str,
+        args: &[&'a str],
+    ) -> Result<(Match, &Command, Vec<&Group>, Vec<&'a str>)> {
+        if args.is_empty() {
+            return Err(anyhow::anyhow!("Usage: ds {} <keys...>", action));
+        }
+
+        let match_ = self.match_command(args)?;
+        let extra_args = args[match_.score..].to_vec();
+        let (command, parents) = self.command_from_match(&match_)?;
+
+        if !command.resolve_service(&parents) {
+            return Err(anyhow::anyhow!(
+                "'{}' is not a service, set \"service\": true to run it in the background",
+                match_.keys.join(" ")
+            ));
+        }
+
+        Ok((match_, command, parents, extra_args))
+    }
+
+    /// Start a service in the background, `ds start <keys...> [env] [args...]`
+    /// - Its output goes to a log file, see `ds logs`
+    pub fn start_service(&mut self, args: &[&str]) -> Result<()> {
+        let selected_env = self.options.selected_env();
+        let (match_, command, parents, extra_args) = self.match_service("start", args)?;
+        let runner = command.runner(
+            &parents,
+            &extra_args,
+            selected_env.as_ref(),
+            &match_.file_path,
+        )?;
+
+        let Runner::Command(cmd_str, mut command, mut options) = runner else {
+            return Err(anyhow::anyhow!("Only commands can be started as a service"));
+        };
+
+        let ignored: Vec<&str> = [
+            ("lock", options.lock.is_some()),
+            ("retry", options.retry.is_some()),
+            ("timeout", options.timeout.is_some()),
+            ("hooks", !options.hooks.is_empty()),
+            ("wait_for", !options.wait_for.is_empty()),
+            ("inputs/outputs", options.inputs_outputs.is_some()),
+            ("watch", options.watch.is_some() || self.options.watch),
+        ]
+        .into_iter()
+        .filter_map(|(setting, set)| set.then_some(setting))
+        .collect();
+        if !ignored.is_empty() {
+            eprintln!(
+                "{}",
+                format!(
+                    "Ignoring {} of {}, they don't apply to services",
+                    ignored.join(", "),
+                    match_.keys.join(" ")
+                )
+                .yellow()
+            );
+        }
+
+        self.confirm(&cmd_str, &command, &options)?;
+
+        let name = match_.keys.join(" ");
+        let log = self.run_log(&match_.file_path, &match_.keys)?.create()?;
+        options.write_script()?;
+        let script = options.script.as_ref().map(|script| script.path.clone());
+        let pid =
+            Services::new(&match_.file_path)?
+                .get(&match_.keys)
+                .start(&mut command, log, script)?;
+
+        // The service still needs its script after ds exits, it is removed by `ds stop`
+        if let Some(script) = options.script.take() {
+            script.keep();
+        }
+
+        println!("{}", format!("Started {} (pid {})", name, pid).green());
+        println!(
+            "{}",
+            format!("Show its output with: ds logs -f {}", name).dim()
+        );
+        Ok(())
+    }
+
+    /// Stop a running service, `ds stop <keys...>`
+    pub fn stop_service(&mut self, args: &[&str]) -> Result<()> {
+        let default_grace_period = self.config.grace_period.map(|d| d.0);
+        let (match_, command, parents, _) = self.match_service("stop", args)?;
+        let grace_period = command
+            .resolve_grace_period(&parents)
+            .or(default_grace_period)
+            .unwrap_or(DEFAULT_GRACE_PERIOD);
+
+        let name = match_.keys.join(" ");
+        match Services::new(&match_.file_path)?
+            .get(&match_.keys)
+            .stop(grace_period)?
+        {
+            Some(pid) => println!("{}", format!("Stopped {} (pid {})", name, pid).green()),
+            None => println!("{}", format!("{} is not running", name).dim()),
+        }
+
+        Ok(())
+    }
+
+    /// Print the services of the current project, `ds status`
+    pub fn print_services(&mut self) -> Result<()> {
+        let mut found = false;
+
+        for path in &self.paths {
+            let services = Services::new(path)?.list()?;
+            if services.is_empty() {
+                continue;
+            }
+
+            found = true;
+            println!("{}", collapse_to_tilde(path).bold());
+            print_status(&services);
+        }
+
+        if !found {
+            println!(
+                "{}",
+                "No services started, start one with: ds start <keys...>".dim()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Wait until probes succeed, `ds wait <keys...>` for the `wait_for` of a command,
+    /// or `ds wait --port <port> --url <url> --file <path> --cmd <command> [--timeout <duration>]`
+    pub fn wait(&mut self, args: &[&str]) -> Result<()> {
+        if args.first().is_some_and(|arg| arg.starts_with("--")) {
+            let mut checks = parse_probes(args)?
+                .into_iter()
+                .map(|wait| ReadyCheck::new(wait, sh_command))
+                .collect::<Result<Vec<_>>>()?;
+            return exit_on_timeout(wait_for(&mut checks, &self.current_dir)?);
+        }
+
+        if args.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Usage: ds wait <keys...> or ds wait --port <port> --url <url> --file <path> --cmd <command> [--timeout <duration>]"
+            ));
+        }
+
+        // Check commands run with the env, shell and root of the command, like when running it
+        let selected_env = self.options.selected_env();
+        let match_ = self.match_command(args)?;
+        let (command, parents) = self.command_from_match(&match_)?;
+        let runner = command.runner(
+            &parents,
+            &args[match_.score..],
+            selected_env.as_ref(),
+            &match_.file_path,
+        )?;
+
+        let Runner::Command(_, _, mut options) = runner else {
+            return Err(anyhow::anyhow!(
+                "'{}' is a group, wait for one of its commands instead",
+                match_.keys.join(" ")
+            ));
+        };
+
+        if options.wait_for.is_empty() {
+            return Err(anyhow::anyhow!(
+                "'{}' has nothing to wait for, add a \"wait_for\" setting",
+                match_.keys.join(" ")
+            ));
+        }
+
+        exit_on_timeout(wait_for(&mut options.wait_for, &match_.file_path)?)
+    }
+
+    /// Run several commands in order, `ds run [--keep-going] <keys...>`
+    /// - Without `--` every argument is a command, e.g. `ds run lint test build`
+    /// - With `--` every part is a command with its env and extra args,
+    ///   e.g. `ds run api build prod -- test --nocapture`
+    /// - Stops at the first failing command, unless `--keep-going` is passed
+    pub fn run_many(&mut self, args: &[&str]) -> Result<()> {
+        let mut args = args;
+        let mut keep_going = false;
+        while let Some((&"--keep-going" | &"-k", rest)) = args.split_first() {
+            keep_going = true;
+            args = rest;
+        }
+
+        let invocations: Vec<&[&str]> = if args.contains(&"--") {
+            args.split(|arg| *arg == "--")
+                .filter(|invocation| !invocation.is_empty())
+                .collect()
+        } else {
+            args.chunks(1).collect()
+        };
+
+        if invocations.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Usage: ds run [--keep-going] <keys...> or ds run <keys> [args...] -- <keys> [args...]"
+            ));
+        }
+
+        // Match all commands first, so a typo doesn't stop the run halfway
+        let selected_env = self.options.selected_env();
+        let mut runners = Vec::new();
+        for invocation in invocations {
+            let match_ = self
+                .match_command(invocation)
+                .with_context(|| format!("Failed to match '{}'", invocation.join(" ")))?;
+            let (command, parents) = self.command_from_match(&match_)?;
+            let runner = command.runner(
+                &parents,
+                &invocation[match_.score..],
+                selected_env.as_ref(),
+                &match_.file_path,
+            )?;
+
+            let Runner::Command(cmd_str, command, options) = runner else {
+                return Err(anyhow::anyhow!(
+                    "'{}' is a group, run one of its commands instead",
+                    match_.keys.join(" ")
+                ));
+            };
+
+            runners.push((invocation.join(" "), match_, cmd_str, command, options));
+        }
+
+        let mut rows = Vec::new();
+        let mut exit_code = 0;
+        let mut stopped = false;
+
+        for (name, match_, cmd_str, command, options) in runners {
+            if stopped {
+                rows.push(RunRow {
+                    name,
+                    finished: None,
+                });
+                continue;
+            }
+
+            let finished = self
+                .execute(&cmd_str, command, *options, &match_.file_path, &match_.keys)
+                .unwrap_or_else(Finished::failed);
+
+            if finished.exit_code != 0 {
+                // Exit with the code of the first failure
+                if exit_code == 0 {
+                    exit_code = finished.exit_code;
+                }
+                stopped = !keep_going || finished.interrupted;
+            }
+
+            rows.push(RunRow {
+                name,
+                finished: Some((finished.exit_code, finished.elapsed)),
+            });
+        }
+
+        print_results(&rows);
+        exit_with(exit_code);
+    }
+
+    /// Print the env vars of a command, `ds env export <keys...> [env] [--format sh|fish|dotenv|json]`
+    /// - The env is matched like when running the command, so `eval "$(ds env export api prod)"` works
+    pub fn export_env(&mut self, args: &[&str]) -> Result<()> {
+        let mut format = ExportFormat::Sh;
+        let mut target = Vec::new();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.split_once('=') {
+                Some(("--format", value)) => format = value.parse()?,
+                _ if *arg == "--format" => {
+                    format = iter
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Missing value for option '{}'", arg))?
+                        .parse()?
+                }
+                _ => target.push(*arg),
+            }
+        }
+
+        if target.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Usage: ds env export <keys...> [env] [--format sh|fish|dotenv|json]"
+            ));
+        }
+
+        let selected_env = self.options.selected_env();
+        let match_ = self.match_command(&target)?;
+        let (command, parents) = self.command_from_match(&match_)?;
+        let (envs, default_env) = command.resolve_envs(&parents);
+
+        if envs.is_empty() {
+            return Err(anyhow::anyhow!(
+                "'{}' has no envs to export",
+                match_.keys.join(" ")
+            ));
+        }
+
+        let positional = command.resolve_positional_envs(&parents);
+        let rest = &target[match_.score..];
+        let Some((key, env, rest)) =
+            match_env(envs, default_env, selected_env.as_ref(), positional, rest)?
+        else {
+            return Err(anyhow::anyhow!(
+                "No env of '{}' matches, nothing to export",
+                match_.keys.join(" ")
+            ));
+        };
+
+        if !rest.is_empty() {
+            return Err(anyhow::anyhow!("Unexpected arguments: {}", rest.join(" ")));
+        }
+
+        let env_vars = env
+            .get_env_vars(&match_.file_path)
+            .with_context(|| format!("Failed to load env '{}'", key))?;
+
+        // The prefix runs around the command, it can't be exported as vars
+        if let Some(prefix) = &env_vars.command {
+            eprintln!(
+                "{}",
+                format!(
+                    "Env '{}' runs commands with `{}`, which is not exported, only its vars are",
+                    key, prefix
+                )
+                .yellow()
+            );
+        }
+
+        let vars = env_vars.vars.unwrap_or_default();
+
+        print!("{}", export_vars(&vars, format)?);
+        Ok(())
+    }
+
+    /// Run the matched command once per env, `ds --all-envs <keys...>` or `ds --envs dev,prod <keys...>`
+    /// - The envs run one after the other, or at the same time with `--parallel`
+    /// - Every env runs, even if one fails, and ds exits with the code of the first failing env
+    fn run_envs(&mut self, match_: &Match, args: &[&str]) -> Result<()> {
+        let results = self.execute_envs(match_, args)?;
+
+        let exit_code = results
+            .iter()
+            .filter_map(|(_, finished)| finished.as_ref())
+            .map(|finished| finished.exit_code)
+            .find(|code| *code != 0)
+            .unwrap_or(0);
+
+        let rows: Vec<RunRow> = results
+            .into_iter()
+            .map(|(env, finished)| RunRow {
+                name: env,
+                finished: finished.map(|f| (f.exit_code, f.elapsed)),
+            })
+            .collect();
+
+        print_results(&rows);
+        exit_with(exit_code);
+    }
+
+    /// Run the matched command for every selected env, getting the outcome of each env
+    /// - An env that can't be prepared or started counts as failed, the other envs still run
+    /// - Envs skipped after Ctrl+C have no outcome
+    fn execute_envs(
+        &mut self,
+        match_: &Match,
+        args: &[&str],
+    ) -> Result<Vec<(String, Option<Finished>)>> {
+        if self.options.env.is_some() {
+            return Err(anyhow::anyhow!(
+                "--env can't be combined with --all-envs or --envs"
+            ));
+        }
+        if self.options.watch {
+            return Err(anyhow::anyhow!(
+                "--watch can't be combined with --all-envs or --envs"
+            ));
+        }
+
+        let name = match_.keys.join(" ");
+        let requested = self.options.envs.clone();
+        let (command, parents) = self.command_from_match(match_)?;
+        let (envs, _) = command.resolve_envs(&parents);
+
+        if envs.is_empty() {
+            return Err(anyhow::anyhow!("'{}' has no envs to run with", name));
+        }
+        if matches!(command, Command::Group(_)) {
+            return Err(anyhow::anyhow!(
+                "'{}' is a group, run one of its commands instead",
+                name
+            ));
+        }
+
+        let selected: Vec<String> = match requested {
+            Some(selected) => selected,
+            None => envs.keys().map(|key| key.to_string()).collect(),
+        };
+
+        if let Some(unknown) = selected
+            .iter()
+            .find(|env| !envs.keys().any(|key| key == env))
+        {
+            let available: Vec<&str> = envs.keys().map(|key| key.as_str()).collect();
+            return Err(anyhow::anyhow!(
+                "'{}' has no env '{}', available envs: {}",
+                name,
+                unknown,
+                available.join(", ")
+            ));
+        }
+
+        // Resolve all runs first, so a missing env file doesn't stop the runs halfway
+        let mut runners = Vec::new();
+        for env in selected {
+            let runner = command
+                .runner(
+                    &parents,
+                    args,
+                    Some(&SelectedEnv::Flag(env.clone())),
+                    &match_.file_path,
+                )
+                .and_then(|runner| match runner {
+                    Runner::Command(cmd_str, command, options) => Ok((cmd_str, command, options)),
+                    Runner::Help => Err(anyhow::anyhow!("'{}' is a group", name)),
+                })
+                .with_context(|| format!("Failed to prepare env '{}'", env));
+
+            runners.push((env, runner));
+        }
+
+        if self.options.parallel {
+            return self.run_envs_parallel(runners, match_);
+        }
+
+        let mut results = Vec::new();
+        let mut stopped = false;
+        for (env, runner) in runners {
+            // Only Ctrl+C stops the remaining envs
+            if stopped {
+                results.push((env, None));
+                continue;
+            }
+
+            eprintln!("{}", format!("[{}]", env).bold());
+            let finished = runner
+                .and_then(|(cmd_str, command, options)| {
+                    self.execute(&cmd_str, command, *options, &match_.file_path, &match_.keys)
+                        .with_context(|| format!("Failed to run env '{}'", env))
+                })
+                .unwrap_or_else(Finished::failed);
+            stopped = finished.interrupted;
+            results.push((env, Some(finished)));
+        }
+
+        Ok(results)
+    }
+
+    /// Run the envs at the same time, with the output of each line labelled with its env
+    /// - Hooks, logs and `wait_for` need the terminal or run around a single command, so they
+    ///   are refused instead of skipped
+    fn run_envs_parallel(
+        &self,
+        runners: Vec<(String, Result<PreparedRun>)>,
+        match_: &Match,
+    ) -> Result<Vec<(String, Option<Finished>)>> {
+        let log = self.config.log.unwrap_or(false);
+        let prepared = || {
+            runners
+                .iter()
+                .filter_map(|(_, runner)| runner.as_ref().ok())
+        };
+        let unsupported: Vec<&str> = [
+            (
+                "hooks",
+                prepared().any(|(.., options)| !options.hooks.is_empty()),
+            ),
+            (
+                "wait_for",
+                prepared().any(|(.., options)| !options.wait_for.is_empty()),
+            ),
+            (
+                "log",
+                prepared().any(|(.., options)| options.log.unwrap_or(log)),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(setting, set)| set.then_some(setting))
+        .collect();
+        if !unsupported.is_empty() {
+            return Err(anyhow::anyhow!(
+                "--parallel can't be used with the {} of '{}', run the envs one at a time instead",
+                unsupported.join(", "),
+                match_.keys.join(" ")
+            ));
+        }
+
+        let width = runners.iter().map(|(env, _)| env.len()).max().unwrap_or(0);
+        let label = |env: &str| format!("{:width$}", format!("[{}]", env), width = width + 2);
+
+        // Skip the envs that are up to date, and ask all confirmations up front, as the
+        // commands don't get the terminal
+        let mut results = Vec::new();
+        let mut pending = Vec::new();
+        for (env, runner) in runners {
+            let checked = runner.and_then(|(cmd_str, command, options)| {
+                let freshness = self.check_inputs(
+                    &cmd_str,
+                    &command,
+                    &options,
+                    &match_.file_path,
+                    &match_.keys,
+                )?;
+                if !matches!(freshness, Freshness::UpToDate) {
+                    self.confirm(&cmd_str, &command, &options)?;
+                }
+                Ok((cmd_str, command, options, freshness))
+            });
+
+            let finished = match checked {
+                Ok((cmd_str, _, _, Freshness::UpToDate)) => {
+                    println!(
+                        "{} {}",
+                        label(&env).bold(),
+                        up_to_date_message(&cmd_str).dim()
+                    );
+                    Some(Finished {
+                        exit_code: 0,
+                        interrupted: false,
+                        elapsed: Duration::ZERO,
+                    })
+                }
+                Ok((cmd_str, command, options, freshness)) => {
+                    let fingerprint = match freshness {
+                        Freshness::Changed(fingerprint) => Some(fingerprint),
+                        _ => None,
+                    };
+                    pending.push((env.clone(), cmd_str, command, options, fingerprint));
+                    None
+                }
+                Err(e) => Some(Finished::failed(
+                    e.context(format!("Failed to run env '{}'", env)),
+                )),
+            };
+            results.push((env, finished));
+        }
+
+        // The envs run the same command, so they share its lock
+        let lock = pending
+            .first()
+            .and_then(|(.., options, _)| options.lock.as_ref())
+            .map(|lock| lock.acquire(&match_.file_path, &match_.keys))
+            .transpose();
+        let _lock = match lock {
+            Ok(lock) => lock,
+            Err(e) => {
+                // None of the envs can run without the lock
+                eprintln!("{}", format!("{:#}", e).red());
+                for (env, ..) in pending {
+                    let finished = Finished {
+                        exit_code: 1,
+                        interrupted: false,
+                        elapsed: Duration::ZERO,
+                    };
+                    set_finished(&mut results, &env, finished);
+                }
+                return Ok(results);
+            }
+        };
+
+        let mut started_envs = Vec::new();
+        for (env, cmd_str, command, options, fingerprint) in pending {
+            match options.write_script() {
+                Ok(()) => started_envs.push((env, cmd_str, command, options, fingerprint)),
+                Err(e) => {
+                    let finished =
+                        Finished::failed(e.context(format!("Failed to run env '{}'", env)));
+                    set_finished(&mut results, &env, finished);
+                }
+            }
+        }
+
+        let outcomes = thread::scope(|scope| {
+            let handles: Vec<_> = started_envs
+                .into_iter()
+                .map(|(env, cmd_str, mut command, options, fingerprint)| {
+                    let label = label(&env);
+                    eprintln!("{} {}", label.clone().bold(), cmd_str.as_str().dim());
+
+                    let handle = scope.spawn(move || {
+                        let started = SystemTime::now();
+                        let process_options = self.process_options(&options);
+                        let outcome = run_labelled(
+                            &mut command,
+                            &label.clone().bold().to_string(),
+                            process_options,
+                            options.retry.as_ref(),
+                        );
+
+                        if let Ok(outcome) = &outcome
+                            && outcome.timed_out
+                            && let Some(timeout) = process_options.timeout
+                        {
+                            eprintln!(
+                                "{} {}",
+                                label.bold(),
+                                timed_out_message(timeout, &cmd_str).red()
+                            );
+                        }
+
+                        (started, options, fingerprint, outcome)
+                    });
+                    (env, handle)
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|(env, handle)| {
+                    let joined = handle
+                        .join()
+                        .map_err(|_| anyhow::anyhow!("The thread of env '{}' panicked", env));
+                    (env, joined)
+                })
+                .collect::<Vec<_>>()
+        });
+
+        for (env, joined) in outcomes {
+            let ran = joined.and_then(|(started, options, fingerprint, outcome)| {
+                Ok((started, options, fingerprint, outcome?))
+            });
+            let (started, options, fingerprint, outcome) = match ran {
+                Ok(ran) => ran,
+                Err(e) => {
+                    let finished =
+                        Finished::failed(e.context(format!("Failed to run env '{}'", env)));
+                    set_finished(&mut results, &env, finished);
+                    continue;
+                }
+            };
+
+            let exit_code = outcome.exit_code();
+            self.record_history(
+                started,
+                &match_.file_path,
+                &match_.keys,
+                &options,
+                exit_code,
+            );
+
+            if exit_code == 0
+                && let Some(fingerprint) = fingerprint
+            {
+                save_fingerprint(fingerprint, &match_.keys, &options);
+            }
+
+            let finished = Finished {
+                exit_code,
+                interrupted: outcome.interrupted,
+                elapsed: started.elapsed().unwrap_or_default(),
+            };
+            set_finished(&mut results, &env, finished);
+        }
+
+        Ok(results)
+    }
+
     /// Run a help row by finding its command and executing it
     pub fn run_help_row(&mut self, row: Option<HelpRow>) -> Result<()> {
         if let Some(row) = row {
             let (command, parents) = self.command_from_help_row(&row)?;
-            let mut args = vec![];
 
-            // Add the environment if any, so the matching logic can pick it up
-            if let Some(env) = &row.env {
-                args.push(env.as_str());
-            }
+            // Select the environment of the row if any, the default env is shown as `(env)`
+            let env = row.env.as_deref().map(|env| {
+                SelectedEnv::Flag(
+                    env.trim_start_matches('(')
+                        .trim_end_matches(')')
+                        .to_string(),
+                )
+            });
 
-            let runner = command.runner(&parents, args.as_slice(), &row.file_path)?;
-            self.run(runner)?;
+            let runner = command.runner(&parents, &[], env.as_ref(), &row.file_path)?;
+            self.run(runner, &row.file_path, &row.key)?;
         }
 
         Ok(())
@@ -189,16 +1308,57 @@ impl DoSomething {
         self.run_help_row(row)
     }
 
+    /// Run a built-in command, e.g. `ds logs` or `ds run`
+    fn run_builtin(&mut self, name: &str, args: &[&str]) -> Result<()> {
+        match (name, args) {
+            ("logs", args) => self.print_logs(args),
+            ("history", args) => self.print_history(args),
+            ("again" | "!!", args) => self.run_again(args),
+            ("start", args) => self.start_service(args),
+            ("stop", args) => self.stop_service(args),
+            ("restart", args) => {
+                self.stop_service(args)?;
+                self.start_service(args)
+            }
+            ("status", []) => self.print_services(),
+            ("status", _) => Err(anyhow::anyhow!("Usage: ds status")),
+            ("wait", args) => self.wait(args),
+            ("run", args) => self.run_many(args),
+            ("env", ["export", args @ ..]) => self.export_env(args),
+            ("env", _) => Err(anyhow::anyhow!(
+                "Usage: ds env export <keys...> [env] [--format sh|fish|dotenv|json]"
+            )),
+            _ => Err(anyhow::anyhow!("Unknown built-in command: {}", name)),
+        }
+    }
+
     /// Run a match based on provided arguments
+    /// - Built-in commands are run first, top level commands with their names are shadowed
     pub fn run_match(&mut self, args_str: &[&str]) -> Result<()> {
+        if let Some((name, args)) = args_str.split_first()
+            && BUILTIN_COMMANDS.contains(name)
+        {
+            return self.run_builtin(name, args);
+        }
+
         // Get the runner based on the provided arguments
+        let selected_env = self.options.selected_env();
         let match_ = self.match_command(args_str)?;
+        if self.options.all_envs || self.options.envs.is_some() {
+            return self.run_envs(&match_, &args_str[match_.score..]);
+        }
+
         let (command, parents) = self.command_from_match(&match_)?;
-        let runner = command.runner(&parents, &args_str[match_.score..], &match_.file_path)?;
+        let runner = command.runner(
+            &parents,
+            &args_str[match_.score..],
+            selected_env.as_ref(),
+            &match_.file_path,
+        )?;
 
         // Execute the runner
         match runner {
-            Runner::Command(_, _) => self.run(runner),
+            Runner::Command(..) => self.run(runner, &match_.file_path, &match_.keys),
             Runner::Help => {
                 let lines = self.help_rows_for_match(&match_)?;
                 let file = self.file_from_match(&match_)?;
@@ -214,7 +1374,7 @@ impl DoSomething {
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, ffi::OsStr};
+    use std::{collections::HashMap, ffi::OsStr, process::Stdio};
 
     use super::*;
 
@@ -225,6 +1385,7 @@ mod tests {
             paths: paths.iter().map(PathBuf::from).collect(),
             current_dir: std::env::current_dir().unwrap(),
             git_root: None,
+            options: CliOptions::default(),
         }
     }
 
@@ -233,13 +1394,15 @@ mod tests {
         let mut ds = make_ds(&["./tests/fixtures/full.json"]);
         let matched = ds.match_command(&["app", "dev"]).unwrap();
         let (command, parents) = ds.command_from_match(&matched).unwrap();
-        let runner = command.runner(&parents, &[], &matched.file_path).unwrap();
+        let runner = command
+            .runner(&parents, &[], None, &matched.file_path)
+            .unwrap();
 
         assert_eq!(matched.score, 2);
         assert_eq!(matched.keys, vec!["app", "with-env", "dev"]);
-        assert_eq!(command.command(), Some("echo app-dev"));
+        assert_eq!(command.command().as_deref(), Some("echo app-dev"));
 
-        if let Runner::Command(cmd_str, command) = runner {
+        if let Runner::Command(cmd_str, command, _) = runner {
             let env_map: HashMap<&OsStr, Option<&OsStr>> = command.get_envs().collect();
             let args = command.get_args().collect::<Vec<&OsStr>>();
 
@@ -266,14 +1429,19 @@ mod tests {
             .unwrap();
         let (command, parents) = ds.command_from_match(&matched).unwrap();
         let runner = command
-            .runner(&parents, &["prod", "--extra-flag"], &matched.file_path)
+            .runner(
+                &parents,
+                &["prod", "--extra-flag"],
+                None,
+                &matched.file_path,
+            )
             .unwrap();
 
         assert_eq!(matched.score, 2);
         assert_eq!(matched.keys, vec!["app", "with-env", "build"]);
-        assert_eq!(command.command(), Some("echo app-build"));
+        assert_eq!(command.command().as_deref(), Some("echo app-build"));
 
-        if let Runner::Command(_cmd_str, command) = runner {
+        if let Runner::Command(_cmd_str, command, _) = runner {
             let args = command.get_args().collect::<Vec<&OsStr>>();
             let env_map: HashMap<&OsStr, Option<&OsStr>> = command.get_envs().collect();
 
@@ -294,4 +1462,296 @@ mod tests {
             panic!("Expected Runner::Command");
         }
     }
+
+    #[test]
+    fn match_build_command_with_selected_env() {
+        let mut ds = make_ds(&["./tests/fixtures/full.json"]);
+        let matched = ds.match_command(&["app", "b", "dev"]).unwrap();
+        let (command, parents) = ds.command_from_match(&matched).unwrap();
+        let runner = command
+            .runner(
+                &parents,
+                &["dev"],
+                Some(&SelectedEnv::Flag("prod".to_string())),
+                &matched.file_path,
+            )
+            .unwrap();
+
+        if let Runner::Command(cmd_str, command, _) = runner {
+            let env_map: HashMap<&OsStr, Option<&OsStr>> = command.get_envs().collect();
+
+            // The selected env wins, so `dev` is passed on as an extra arg
+            assert_eq!(cmd_str, "COMMAND_VAR=production echo app-build dev");
+            assert_eq!(
+                env_map.get(OsStr::new("ENVIRONMENT")),
+                Some(&Some(OsStr::new("production")))
+            );
+        } else {
+            panic!("Expected Runner::Command");
+        }
+    }
+
+    #[test]
+    fn hide_commands_with_unmet_conditions() {
+        let mut ds = make_ds(&["./tests/fixtures/when.json"]);
+
+        let matched = ds.match_command(&["existing-file"]).unwrap();
+        assert_eq!(matched.keys, vec!["existing-file"]);
+
+        let rows = ds.help_groups().unwrap().0.remove(0).rows;
+        let keys = rows
+            .iter()
+            .map(|row| row.key.join(" "))
+            .collect::<Vec<String>>();
+        assert_eq!(keys, vec!["existing-file", "tool"]);
+
+        let err = ds.match_command(&["docker", "up"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No matching command found\n  'docker up' is not available: ds-missing-docker is not on PATH"
+        );
+
+        let err = ds.match_command(&["unknown"]).unwrap_err();
+        assert_eq!(err.to_string(), "No matching command found");
+    }
+
+    #[test]
+    fn load_commands_named_like_builtins() {
+        let mut ds = make_ds(&["./tests/fixtures/builtins.json"]);
+
+        let matched = ds.match_command(&["test"]).unwrap();
+        let file = ds.file_from_match(&matched).unwrap();
+        assert_eq!(
+            file.group.shadowed_commands(),
+            vec![("run".to_string(), "run"), ("tail".to_string(), "logs")]
+        );
+
+        let rows = ds.help_groups().unwrap().0.remove(0).rows;
+        let keys = rows
+            .iter()
+            .map(|row| row.key.join(" "))
+            .collect::<Vec<String>>();
+        assert_eq!(keys, vec!["run", "tail", "test"]);
+
+        let (command, parents) = ds.command_from_match(&matched).unwrap();
+        let runner = command
+            .runner(&parents, &[], None, &matched.file_path)
+            .unwrap();
+        let Runner::Command(_, mut command, _) = runner else {
+            panic!("Expected Runner::Command");
+        };
+
+        let output = command.stdout(Stdio::piped()).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "test\n");
+    }
+
+    #[test]
+    fn run_every_env_even_if_one_fails_to_start() {
+        for parallel in [false, true] {
+            let mut ds = make_ds(&["./tests/fixtures/envs.json"]);
+            ds.options.all_envs = true;
+            ds.options.parallel = parallel;
+
+            let matched = ds.match_command(&["check"]).unwrap();
+            let results = ds.execute_envs(&matched, &[]).unwrap();
+            let exit_codes = results
+                .iter()
+                .map(|(env, finished)| (env.as_str(), finished.as_ref().map(|f| f.exit_code)))
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                exit_codes,
+                vec![("broken", Some(1)), ("working", Some(0))],
+                "parallel: {}",
+                parallel
+            );
+        }
+    }
+
+    #[test]
+    fn expand_matrix_commands() {
+        let mut ds = make_ds(&["./tests/fixtures/matrix.json"]);
+
+        let rows = ds.help_groups().unwrap().0.remove(0).rows;
+        let keys = rows
+            .iter()
+            .map(|row| row.key.join(" "))
+            .collect::<Vec<String>>();
+        assert_eq!(
+            keys,
+            vec![
+                "api logs-eu",
+                "api logs-us",
+                "deploy-api",
+                "deploy-web",
+                "worker logs-eu",
+                "worker logs-us"
+            ]
+        );
+
+        let matched = ds.match_command(&["worker", "logs-us"]).unwrap();
+        let (command, _) = ds.command_from_match(&matched).unwrap();
+        assert_eq!(command.command(), Some("echo logs worker us".to_string()));
+    }
+
+    #[test]
+    fn match_shell_and_exec_commands() {
+        struct Case {
+            name: &'static str,
+            target: Vec<&'static str>,
+            expected_program: &'static str,
+            expected_args: Vec<&'static str>,
+        }
+
+        let cases = [
+            Case {
+                name: "Default shell",
+                target: vec!["default-shell", "it's"],
+                expected_program: "sh",
+                expected_args: vec!["-c", "echo default 'it'\\''s'"],
+            },
+            Case {
+                name: "Shell from the group",
+                target: vec!["bash-group", "arrays"],
+                expected_program: "bash",
+                expected_args: vec!["-c", "arr=(a b); echo ${arr[1]}"],
+            },
+            Case {
+                name: "Custom shell argv on the command",
+                target: vec!["bash-group", "python"],
+                expected_program: "python3",
+                expected_args: vec!["-c", "print('hello')"],
+            },
+            Case {
+                name: "Exec command, extra args are passed as is",
+                target: vec!["exec", "it's", "a test"],
+                expected_program: "cargo",
+                expected_args: vec!["test", "--", "it's", "a test"],
+            },
+            Case {
+                name: "Custom shell argv, extra args after the command",
+                target: vec!["bash-group", "python", "it's"],
+                expected_program: "python3",
+                expected_args: vec!["-c", "print('hello')", "it's"],
+            },
+            Case {
+                name: "Non-POSIX shell, extra args after the command",
+                target: vec!["fish-group", "greet", "a b"],
+                expected_program: "fish",
+                expected_args: vec!["-c", "echo hello $argv", "a b"],
+            },
+            Case {
+                name: "Exec command with a prefix, run with the shell",
+                target: vec!["bash-group", "exec-prefixed", "secrets", "x"],
+                expected_program: "bash",
+                expected_args: vec!["-c", "op run -- \"$@\"", "bash", "cargo", "test", "x"],
+            },
+            Case {
+                name: "Exec command with a prefix, non-POSIX shell falls back to sh",
+                target: vec!["fish-group", "exec-prefixed", "secrets", "x"],
+                expected_program: "sh",
+                expected_args: vec!["-c", "op run -- \"$@\"", "sh", "cargo", "test", "x"],
+            },
+        ];
+
+        for case in cases {
+            let mut ds = make_ds(&["./tests/fixtures/shells.json"]);
+            let matched = ds.match_command(&case.target).unwrap();
+            let (command, parents) = ds.command_from_match(&matched).unwrap();
+            let args = &case.target[matched.score..];
+            let runner = command
+                .runner(&parents, args, None, &matched.file_path)
+                .unwrap();
+
+            if let Runner::Command(_, command, _) = runner {
+                let args = command.get_args().collect::<Vec<&OsStr>>();
+
+                assert_eq!(
+                    command.get_program(),
+                    case.expected_program,
+                    "{}",
+                    case.name
+                );
+                assert_eq!(args, case.expected_args, "{}", case.name);
+            } else {
+                panic!("Expected Runner::Command");
+            }
+        }
+    }
+
+    #[test]
+    fn run_scripts() {
+        struct Case {
+            name: &'static str,
+            target: Vec<&'static str>,
+            expected_cmd_str: &'static str,
+            expected_program: Option<&'static str>,
+            expected_output: &'static str,
+        }
+
+        let cases = [
+            Case {
+                name: "Without shebang, run with sh",
+                target: vec!["default", "a b"],
+                expected_cmd_str: "set -e … 'a b'",
+                expected_program: Some("sh"),
+                expected_output: "default a b\n",
+            },
+            Case {
+                name: "With shebang, run directly",
+                target: vec!["shebang", "x"],
+                expected_cmd_str: "echo \"shebang $1\" … x",
+                expected_program: None,
+                expected_output: "shebang x\ndone\n",
+            },
+            Case {
+                name: "With interpreter",
+                target: vec!["interpreter", "y"],
+                expected_cmd_str: "import sys … y",
+                expected_program: Some("python3"),
+                expected_output: "python y\n",
+            },
+            Case {
+                name: "Without shebang, run with the shell of the command",
+                target: vec!["bash-shell", "z"],
+                expected_cmd_str: "arr=(a b) … z",
+                expected_program: Some("bash"),
+                expected_output: "bash b z\n",
+            },
+        ];
+
+        for case in cases {
+            let mut ds = make_ds(&["./tests/fixtures/scripts.json"]);
+            let matched = ds.match_command(&case.target).unwrap();
+            let (command, parents) = ds.command_from_match(&matched).unwrap();
+            let args = &case.target[matched.score..];
+            let runner = command
+                .runner(&parents, args, None, &matched.file_path)
+                .unwrap();
+
+            let Runner::Command(cmd_str, mut command, options) = runner else {
+                panic!("Expected Runner::Command");
+            };
+
+            let script = options.script.as_ref().unwrap().path.clone();
+            assert!(!script.exists(), "Only written when run: {}", case.name);
+            options.write_script().unwrap();
+            assert!(script.exists(), "{}", case.name);
+            assert_eq!(cmd_str, case.expected_cmd_str, "{}", case.name);
+            if let Some(program) = case.expected_program {
+                assert_eq!(command.get_program(), program, "{}", case.name);
+            }
+
+            let output = command.stdout(Stdio::piped()).output().unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                case.expected_output,
+                "{}",
+                case.name
+            );
+
+            drop(options);
+            assert!(!script.exists(), "Removed after the run: {}", case.name);
+        }
+    }
 }
@@ -3,8 +3,10 @@ use crate::{
     dir::collapse_to_tilde,
     group::{Group, Walk},
     help::{HelpGroup, HelpRow},
+    matrix,
 };
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
+use crossterm::style::Stylize;
 use std::{
     fs,
     path::{Path, PathBuf},
@@ -75,6 +77,28 @@ impl DsFile {
     /// Load a group configuration from a JSON string and path.
     pub fn from_json(json: String, path: impl AsRef<Path>) -> Result<Self> {
         let mut group: Group = serde_json::from_str(&json)?;
+
+        // Expand the matrices on the JSON, so their values end up in every setting
+        if group.has_matrix() {
+            let mut value: serde_json::Value = serde_json::from_str(&json)?;
+            matrix::expand(&mut value)?;
+            group = serde_json::from_value(value)?;
+        }
+        group
+            .validate()
+            .with_context(|| format!("Invalid ds file {}", path.as_ref().display()))?;
+        for (keys, name) in group.shadowed_commands() {
+            eprintln!(
+                "{}",
+                format!(
+                    "`ds {}` runs the built-in command instead of '{}' in {}, rename it or move it into a group",
+                    name,
+                    keys,
+                    collapse_to_tilde(path.as_ref())
+                )
+                .yellow()
+            );
+        }
         let file_name = path
             .as_ref()
             .file_name()
@@ -113,7 +137,8 @@ impl DsFile {
 
     /// Get a command (and its parents) from the tree, based on the provided keys
     pub fn command_from_keys(&self, keys: &[String]) -> Result<(&Command, Vec<&Group>)> {
-        let mut parents: Vec<&Group> = Vec::new();
+        // The file itself is the outermost group, so its settings are inherited too
+        let mut parents: Vec<&Group> = vec![&self.group];
         let mut command = None;
 
         for key in keys {
@@ -209,6 +234,48 @@ impl DsFile {
         Ok(res)
     }
 
+    /// Get the commands that would match, but are hidden by an unmet `when` condition
+    /// - Returns the match with the condition that is not met, to explain why it is hidden
+    pub fn hidden_matches(&self, target: &[&str]) -> Result<Vec<(Match, String)>> {
+        let mut matches = Vec::new();
+        let mut err = None;
+
+        self.group.walk_commands(&mut |keys, cmd, parents| {
+            // The condition of a hidden group applies to all of its commands
+            let unmet = parents
+                .iter()
+                .filter_map(|group| group.when.as_ref())
+                .map(|when| when.unmet(&self.path))
+                .chain([cmd.unmet_condition(&self.path)])
+                .find_map(Result::transpose);
+
+            let reason = match unmet {
+                None => return Walk::Continue,
+                Some(Err(e)) => {
+                    err = Some(e);
+                    return Walk::Stop;
+                }
+                Some(Ok(reason)) => reason,
+            };
+
+            let command_keys = cmd.resolve_aliases(keys, parents);
+            if let Some(m) = Match::from_command(self.path.clone(), keys, &command_keys, target) {
+                matches.push((m, reason));
+            }
+
+            Walk::Continue
+        });
+
+        if let Some(err) = err {
+            return Err(err);
+        }
+
+        let max_depth = matches.iter().map(|(m, _)| m.score).max().unwrap_or(0);
+        matches.retain(|(m, _)| m.score == max_depth);
+
+        Ok(matches)
+    }
+
     /// Get the help rows for a match in the command file
     pub fn help_rows_for_match(
         &self,
@@ -333,4 +400,80 @@ mod tests {
             assert_eq!(score, case.expected_score, "{}", case.name);
         }
     }
+
+    #[test]
+    fn reject_commands_without_something_to_run() {
+        struct Case {
+            name: &'static str,
+            json: &'static str,
+            expected: Result<(), &'static str>,
+        }
+
+        let cases = [
+            Case {
+                name: "Command and group",
+                json: r#"{"commands": {"api": {"commands": {"dev": {"command": "pnpm dev"}}}}}"#,
+                expected: Ok(()),
+            },
+            Case {
+                name: "Typo in the command field",
+                json: r#"{"commands": {"api": {"commands": {"dev": {"comand": "pnpm dev"}}}}}"#,
+                expected: Err(
+                    "Invalid ds file /fixtures/ds.json: Invalid command 'api dev': A command needs a `command` or a `script`",
+                ),
+            },
+            Case {
+                name: "Both a command and a script",
+                json: r#"{"commands": {"both": {"command": "echo", "script": "echo"}}}"#,
+                expected: Err(
+                    "Invalid ds file /fixtures/ds.json: Invalid command 'both': A command can't have both a `command` and a `script`",
+                ),
+            },
+            Case {
+                name: "Built-in command on the top level, only warns",
+                json: r#"{"commands": {"run": "cargo run --", "test": "cargo test"}}"#,
+                expected: Ok(()),
+            },
+            Case {
+                name: "Built-in command in a group",
+                json: r#"{"commands": {"api": {"commands": {"run": "echo", "status": "echo"}}}}"#,
+                expected: Ok(()),
+            },
+            Case {
+                name: "Inputs outside the root",
+                json: r#"{"commands": {"gen": {"command": "codegen", "inputs": ["src/**", "../shared/*.graphql"]}}}"#,
+                expected: Err(
+                    "Invalid ds file /fixtures/ds.json: Invalid command 'gen': `inputs` and `outputs` have to be inside the root of the command, got: ../shared/*.graphql",
+                ),
+            },
+            Case {
+                name: "Invalid pattern of a required var in an env",
+                json: r#"{"commands": {"deploy": {"command": "echo", "envs": {"prod": {"required_vars": [{"name": "TOKEN", "pattern": "^ghp_(["}]}}}}}"#,
+                expected: Err(
+                    "Invalid ds file /fixtures/ds.json: Invalid command 'deploy': Invalid pattern for required var 'TOKEN': regex parse error:",
+                ),
+            },
+            Case {
+                name: "Invalid pattern of a required var in the file",
+                json: r#"{"required_vars": [{"name": "PORT", "pattern": "*"}], "commands": {"dev": "echo"}}"#,
+                expected: Err(
+                    "Invalid ds file /fixtures/ds.json: Invalid pattern for required var 'PORT': regex parse error:",
+                ),
+            },
+        ];
+
+        for case in cases {
+            // Only the first line, regex errors point at the problem on the next lines
+            let result = DsFile::from_json(case.json.to_string(), "/fixtures/ds.json")
+                .map(|_| ())
+                .map_err(|e| {
+                    format!("{:#}", e)
+                        .lines()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string()
+                });
+            assert_eq!(result, case.expected.map_err(String::from), "{}", case.name);
+        }
+    }
 }
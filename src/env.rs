@@ -1,17 +1,247 @@
-use crate::dir::resolve_path;
+use crate::{config::one_or_many, dir::resolve_path};
 use anyhow::Result;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, path::Path};
+use std::{collections::BTreeMap, path::Path, process::Command as ProcessCommand};
 
-/// Environment configuration, a dotenv file path
+/// The kind of value a required variable must hold
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VarType {
+    /// Any value, including an empty one
+    Any,
+    /// Any value that is not empty or only whitespace
+    NonEmpty,
+    /// A whole number
+    Int,
+    /// Any number, including decimals
+    Number,
+    /// `true`, `false`, `1` or `0`
+    Bool,
+    /// A URL with a scheme, e.g. `https://example.com`
+    Url,
+}
+
+/// A required variable with constraints on its value
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct EnvConfig {
+pub struct RequiredVarConfig {
+    /// The name of the variable
+    pub name: String,
+    /// Optional type the value must match
+    #[serde(rename = "type")]
+    pub var_type: Option<VarType>,
+    /// Optional regex the value must match
+    pub pattern: Option<String>,
+}
+
+/// A variable that must be set before a command is run
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RequiredVar {
+    /// Only the name, the variable must be set
+    Name(String),
+    /// A variable with constraints on its value
+    Config(RequiredVarConfig),
+}
+
+impl RequiredVar {
+    /// Get the name of the variable
+    pub fn name(&self) -> &str {
+        match self {
+            RequiredVar::Name(name) => name,
+            RequiredVar::Config(config) => &config.name,
+        }
+    }
+
+    /// Check a value against the constraints, returning the problem if there is one
+    pub fn validate(&self, value: Option<&str>) -> Result<Option<String>> {
+        let Some(value) = value else {
+            return Ok(Some("missing".to_string()));
+        };
+
+        let RequiredVar::Config(config) = self else {
+            return Ok(None);
+        };
+
+        let valid = match config.var_type.unwrap_or(VarType::Any) {
+            VarType::Any => true,
+            VarType::NonEmpty => !value.trim().is_empty(),
+            VarType::Int => value.parse::<i64>().is_ok(),
+            VarType::Number => value.parse::<f64>().is_ok(),
+            VarType::Bool => matches!(value, "true" | "false" | "1" | "0"),
+            VarType::Url => is_url(value),
+        };
+
+        if !valid {
+            let expected = match config.var_type.unwrap_or(VarType::Any) {
+                VarType::Any => "a value",
+                VarType::NonEmpty => "a non-empty value",
+                VarType::Int => "an integer",
+                VarType::Number => "a number",
+                VarType::Bool => "a boolean",
+                VarType::Url => "a URL",
+            };
+
+            // The value itself is not shown, as it might be a secret
+            return Ok(Some(format!("expected {}", expected)));
+        }
+
+        if let Some(regex) = self.regex()?
+            && !regex.is_match(value)
+        {
+            return Ok(Some(format!("does not match '{}'", regex.as_str())));
+        }
+
+        Ok(None)
+    }
+
+    /// Compile the pattern of the variable, if it has one
+    /// - Checked when the ds file is loaded, so a typo in it is not reported as an invalid value
+    pub fn regex(&self) -> Result<Option<Regex>> {
+        let RequiredVar::Config(RequiredVarConfig {
+            name,
+            pattern: Some(pattern),
+            ..
+        }) = self
+        else {
+            return Ok(None);
+        };
+
+        Regex::new(pattern)
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid pattern for required var '{}': {}", name, e))
+    }
+}
+
+/// Check that the patterns of the required variables are valid regexes,
+/// including the ones of the envs
+pub fn validate_required_vars(
+    required: Option<&Vec<RequiredVar>>,
+    envs: Option<&BTreeMap<String, Env>>,
+) -> Result<()> {
+    let env_vars = envs
+        .into_iter()
+        .flatten()
+        .flat_map(|(_, env)| env.required_vars());
+
+    for var in required.into_iter().flatten().chain(env_vars) {
+        var.regex()?;
+    }
+
+    Ok(())
+}
+
+/// Check if a value looks like a URL, e.g. `scheme://rest`
+fn is_url(value: &str) -> bool {
+    match value.split_once("://") {
+        Some((scheme, rest)) => {
+            !rest.is_empty()
+                && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+/// Check all required variables against the variables the command will be run with.
+/// - Reports every missing or invalid variable at once
+/// - Mentions the env and the file the variables were expected from
+pub fn check_required_vars(
+    required: &[&RequiredVar],
+    vars: &BTreeMap<String, String>,
+    env_key: Option<&str>,
+    env_paths: &[&str],
+) -> Result<()> {
+    let mut problems = Vec::new();
+
+    for var in required {
+        let name = var.name();
+        let value = vars.get(name).map(|v| v.as_str());
+
+        if let Some(problem) = var.validate(value)? {
+            problems.push(format!("  - {}: {}", name, problem));
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let source = match env_key {
+        Some(key) if !env_paths.is_empty() => {
+            format!(" in env '{}' ({})", key, env_paths.join(", "))
+        }
+        Some(key) => format!(" in env '{}'", key),
+        None => "".to_string(),
+    };
+
+    Err(anyhow::anyhow!(
+        "Missing or invalid environment variables{}:\n{}",
+        source,
+        problems.join("\n")
+    ))
+}
+
+/// A dotenv file with additional configuration
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct EnvFileConfig {
     /// The path to the dotenv file
-    pub path: Option<String>,
+    pub path: String,
+    /// Skip the file silently if it does not exist
+    #[serde(default)]
+    pub optional: bool,
+}
+
+/// A dotenv file in an environment configuration
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EnvFile {
+    /// A dotenv file path, the file must exist
+    Path(String),
+    /// A dotenv file with specific configuration
+    Config(EnvFileConfig),
+}
+
+impl EnvFile {
+    /// Get the path to the dotenv file
+    pub fn path(&self) -> &str {
+        match self {
+            EnvFile::Path(path) => path,
+            EnvFile::Config(config) => &config.path,
+        }
+    }
+
+    /// Check if the file can be skipped when it does not exist
+    pub fn optional(&self) -> bool {
+        match self {
+            EnvFile::Path(_) => false,
+            EnvFile::Config(config) => config.optional,
+        }
+    }
+}
+
+/// Environment configuration, a dotenv file path
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct EnvConfig {
+    /// The path to the dotenv file, or a list of files loaded in order (later files win)
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub path: Option<Vec<EnvFile>>,
     /// List of specific variables to load from the command output
     pub vars: Option<BTreeMap<String, String>>,
     /// What to prefix the command with when running to load environment variables
     pub command_prefix: Option<String>,
+    /// Variables that must be set when running with this environment
+    pub required_vars: Option<Vec<RequiredVar>>,
+    /// Start from an empty environment, instead of inheriting the current shell environment
+    pub clean_env: Option<bool>,
+    /// Variables to keep from the current shell environment, when `clean_env` is set
+    pub inherit: Option<Vec<String>>,
+    /// Variables to remove from the inherited environment
+    pub unset: Option<Vec<String>>,
+    /// Ask for confirmation before running with this environment, by typing its name
+    pub confirm: Option<bool>,
 }
 
 /// An environment definition, either a dotenv file or a command to load envs
@@ -24,7 +254,64 @@ pub enum Env {
     Config(EnvConfig),
 }
 
+/// Controls which variables of the current shell environment are passed on to the command
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct EnvIsolation {
+    /// Start from an empty environment
+    pub clean: bool,
+    /// Variables to keep from the current shell environment, when starting clean
+    pub inherit: Vec<String>,
+    /// Variables to remove from the inherited environment
+    pub unset: Vec<String>,
+}
+
+impl EnvIsolation {
+    /// Merge in isolation settings, clean wins and the lists are combined
+    pub fn merge(
+        &mut self,
+        clean_env: Option<bool>,
+        inherit: Option<&Vec<String>>,
+        unset: Option<&Vec<String>>,
+    ) {
+        self.clean |= clean_env.unwrap_or(false);
+        self.inherit.extend(inherit.into_iter().flatten().cloned());
+        self.unset.extend(unset.into_iter().flatten().cloned());
+    }
+
+    /// Check if a variable of the current shell environment is passed on
+    fn is_inherited(&self, key: &str) -> bool {
+        (!self.clean || self.inherit.iter().any(|k| k == key))
+            && !self.unset.iter().any(|k| k == key)
+    }
+
+    /// Get the variables of the current shell environment that are passed on
+    pub fn inherited_vars(&self) -> BTreeMap<String, String> {
+        std::env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(key, _)| self.is_inherited(key))
+            .collect()
+    }
+
+    /// Apply the isolation to a process command, before any custom variables are set
+    pub fn apply(&self, cmd: &mut ProcessCommand) {
+        if self.clean {
+            cmd.env_clear();
+
+            for key in &self.inherit {
+                if let Some(value) = std::env::var_os(key) {
+                    cmd.env(key, value);
+                }
+            }
+        }
+
+        for key in &self.unset {
+            cmd.env_remove(key);
+        }
+    }
+}
+
 /// The environment variables and/or command to actually run
+#[derive(Debug)]
 pub struct RunnerEnv {
     pub command: Option<String>,
     pub vars: Option<BTreeMap<String, String>>,
@@ -32,19 +319,31 @@ pub struct RunnerEnv {
 
 fn load_env(
     file_path: impl AsRef<Path>,
-    path: Option<impl AsRef<Path>>,
+    files: &[EnvFile],
     config_vars: Option<BTreeMap<String, String>>,
     command_prefix: Option<String>,
 ) -> Result<RunnerEnv> {
-    let mut env_vars = if let Some(path) = path {
-        let full_path = resolve_path(path, &file_path)?;
-        // Load from dotenv file
-        dotenvy::from_path_iter(full_path)?
-            .filter_map(|item| item.ok())
-            .collect()
-    } else {
-        BTreeMap::new()
-    };
+    let mut env_vars = BTreeMap::new();
+
+    // Load the dotenv files in order, so later files override earlier ones
+    for file in files {
+        let full_path = resolve_path(file.path(), &file_path)?;
+
+        if !full_path.exists() {
+            if file.optional() {
+                continue;
+            }
+
+            return Err(anyhow::anyhow!(
+                "Dotenv file not found: {}",
+                full_path.display()
+            ));
+        }
+
+        for (key, value) in dotenvy::from_path_iter(full_path)?.filter_map(|item| item.ok()) {
+            env_vars.insert(key, value);
+        }
+    }
 
     // Add extra vars if specificied
     if let Some(vars) = &config_vars {
@@ -60,13 +359,48 @@ fn load_env(
 }
 
 impl Env {
+    /// Get the dotenv file paths, in the order they are loaded
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            Env::Dotenv(path) => vec![path],
+            Env::Config(config) => config.path.iter().flatten().map(EnvFile::path).collect(),
+        }
+    }
+
+    /// Merge the isolation settings of this environment
+    pub fn merge_isolation(&self, isolation: &mut EnvIsolation) {
+        if let Env::Config(config) = self {
+            isolation.merge(
+                config.clean_env,
+                config.inherit.as_ref(),
+                config.unset.as_ref(),
+            );
+        }
+    }
+
+    /// Check if running with this environment has to be confirmed
+    pub fn confirm(&self) -> bool {
+        match self {
+            Env::Dotenv(_) => false,
+            Env::Config(config) => config.confirm.unwrap_or(false),
+        }
+    }
+
+    /// Get the variables required by this environment
+    pub fn required_vars(&self) -> Vec<&RequiredVar> {
+        match self {
+            Env::Dotenv(_) => vec![],
+            Env::Config(config) => config.required_vars.iter().flatten().collect(),
+        }
+    }
+
     /// Get the environment variables and/or command to run from the config
     pub fn get_env_vars(&self, file_path: impl AsRef<Path>) -> Result<RunnerEnv> {
         match self {
-            Env::Dotenv(path) => load_env(file_path, Some(path), None, None),
+            Env::Dotenv(path) => load_env(file_path, &[EnvFile::Path(path.clone())], None, None),
             Env::Config(config) => load_env(
                 file_path,
-                config.path.as_ref(),
+                config.path.as_deref().unwrap_or_default(),
                 config.vars.clone(),
                 config.command_prefix.clone(),
             ),
@@ -74,29 +408,146 @@ impl Env {
     }
 }
 
+/// The format to export environment variables in, e.g. to load them into a shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// `export KEY='value'` for POSIX shells
+    Sh,
+    /// `set -gx KEY 'value'` for fish
+    Fish,
+    /// `KEY='value'` lines for dotenv files
+    Dotenv,
+    /// A JSON object
+    Json,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sh" | "bash" | "zsh" => Ok(ExportFormat::Sh),
+            "fish" => Ok(ExportFormat::Fish),
+            "dotenv" => Ok(ExportFormat::Dotenv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(anyhow::anyhow!(
+                "Unknown export format '{}', expected sh, fish, dotenv or json",
+                s
+            )),
+        }
+    }
+}
+
+/// Format the variables so they can be evaluated by a shell, or read by other tools
+pub fn export_vars(vars: &BTreeMap<String, String>, format: ExportFormat) -> Result<String> {
+    let mut output = String::new();
+    for (key, value) in vars {
+        let line = match format {
+            ExportFormat::Sh => format!(
+                "export {}={}",
+                key,
+                shell_escape::unix::escape(value.into())
+            ),
+            ExportFormat::Fish => format!(
+                "set -gx {} '{}'",
+                key,
+                value.replace('\\', "\\\\").replace('\'', "\\'")
+            ),
+            // Single quotes keep the value as is, double quotes are needed for quotes and newlines
+            ExportFormat::Dotenv if !value.contains(['\'', '\n']) => format!("{}='{}'", key, value),
+            ExportFormat::Dotenv => format!(
+                "{}=\"{}\"",
+                key,
+                value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('$', "\\$")
+                    .replace('\n', "\\n")
+            ),
+            ExportFormat::Json => {
+                return Ok(format!("{}\n", serde_json::to_string_pretty(vars)?));
+            }
+        };
+
+        output.push_str(&line);
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+/// An environment selected explicitly, instead of matched from the args
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelectedEnv {
+    /// Selected for this run, e.g. with `--env`
+    Flag(String),
+    /// Selected with the `DS_ENV` variable, which might be exported for every command
+    Var(String),
+}
+
+impl SelectedEnv {
+    /// Get the key of the selected environment
+    pub fn key(&self) -> &str {
+        match self {
+            SelectedEnv::Flag(key) | SelectedEnv::Var(key) => key,
+        }
+    }
+}
+
 /// Match an environment from the provided args and default
+/// - An explicitly selected env (`--env` or `DS_ENV`) takes precedence over the args
+/// - Selecting an env with `--env` for a command without envs is an error, `DS_ENV` is ignored
+/// - Only matches the first arg if positional matching is enabled
+/// - Returns the key of the matched environment, the environment, and the remaining args
 pub fn match_env<'a>(
     envs: BTreeMap<&'a String, &'a Env>,
     default_env: Option<&'a str>,
+    selected_env: Option<&SelectedEnv>,
+    positional: bool,
     args: &'a [&'a str],
-) -> Result<Option<(&'a Env, &'a [&'a str])>> {
+) -> Result<Option<(&'a str, &'a Env, &'a [&'a str])>> {
     if envs.is_empty() {
-        return Ok(None);
+        return match selected_env {
+            Some(SelectedEnv::Flag(selected)) => Err(anyhow::anyhow!(
+                "Environment '{}' selected, but the command has no environments",
+                selected
+            )),
+            _ => Ok(None),
+        };
+    }
+
+    if let Some(selected) = selected_env.map(SelectedEnv::key) {
+        return match envs.get_key_value(&selected.to_string()) {
+            Some((&key, &env)) => Ok(Some((key, env, args))),
+            None => Err(anyhow::anyhow!(
+                "Environment '{}' not found, available environments: {}",
+                selected,
+                envs.keys()
+                    .map(|k| k.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        };
     }
 
     // If there are environments defined, but no args and no default, return an error
-    if args.is_empty() && !envs.is_empty() && default_env.is_none() {
+    if (args.is_empty() || !positional) && default_env.is_none() {
         return Err(anyhow::anyhow!(
             "No environment specified, and no default environment is set"
         ));
     }
 
-    if let Some(&env) = args.first().and_then(|&s| envs.get(&s.to_string())) {
-        Ok(Some((env, &args[1..])))
+    let positional_env = args
+        .first()
+        .filter(|_| positional)
+        .and_then(|&s| envs.get_key_value(&s.to_string()));
+
+    if let Some((&key, &env)) = positional_env {
+        Ok(Some((key, env, &args[1..])))
     } else {
         if let Some(default_key) = default_env {
-            if let Some(&env) = envs.get(&default_key.to_string()) {
-                return Ok(Some((env, args)));
+            if let Some((&key, &env)) = envs.get_key_value(&default_key.to_string()) {
+                return Ok(Some((key, env, args)));
             } else {
                 return Err(anyhow::anyhow!(
                     "Environment not found, and default environment '{}' is not found",
@@ -135,6 +586,54 @@ pub fn get_env_by_key<'a>(
 mod tests {
     use super::*;
 
+    #[test]
+    fn export_formats() {
+        struct Case {
+            name: &'static str,
+            format: ExportFormat,
+            expected: &'static str,
+        }
+
+        let vars = BTreeMap::from([
+            ("API_URL".to_string(), "https://api.example.com".to_string()),
+            ("GREETING".to_string(), "it's \"$HOME\"".to_string()),
+        ]);
+
+        let cases = [
+            Case {
+                name: "sh",
+                format: ExportFormat::Sh,
+                expected: "export API_URL='https://api.example.com'\nexport GREETING='it'\\''s \"$HOME\"'\n",
+            },
+            Case {
+                name: "fish",
+                format: ExportFormat::Fish,
+                expected: "set -gx API_URL 'https://api.example.com'\nset -gx GREETING 'it\\'s \"$HOME\"'\n",
+            },
+            Case {
+                name: "dotenv",
+                format: ExportFormat::Dotenv,
+                expected: "API_URL='https://api.example.com'\nGREETING=\"it's \\\"\\$HOME\\\"\"\n",
+            },
+            Case {
+                name: "json",
+                format: ExportFormat::Json,
+                expected: "{\n  \"API_URL\": \"https://api.example.com\",\n  \"GREETING\": \"it's \\\"$HOME\\\"\"\n}\n",
+            },
+        ];
+
+        for case in cases {
+            assert_eq!(
+                export_vars(&vars, case.format).unwrap(),
+                case.expected,
+                "{}",
+                case.name
+            );
+        }
+
+        assert!("powershell".parse::<ExportFormat>().is_err());
+    }
+
     #[test]
     fn test_match_env() {
         let mut envs: BTreeMap<String, Env> = BTreeMap::new();
@@ -143,36 +642,215 @@ mod tests {
             "prod".to_string(),
             Env::Config(EnvConfig {
                 vars: Some([("MODE".to_string(), "prod".to_string())].into()),
-                path: None,
-                command_prefix: None,
+                ..Default::default()
             }),
         );
 
         let envs_ref: BTreeMap<&String, &Env> = envs.iter().collect();
 
-        let result = match_env(BTreeMap::new(), None, &["dev"]).unwrap();
+        let result = match_env(BTreeMap::new(), None, None, true, &["dev"]).unwrap();
         assert!(result.is_none(), "no envs should return None");
 
-        let result = match_env(envs_ref.clone(), None, &["dev", "extra"]).unwrap();
-        let (env, remaining) = result.unwrap();
+        let var = SelectedEnv::Var("prod".to_string());
+        let result = match_env(BTreeMap::new(), None, Some(&var), true, &[]).unwrap();
+        assert!(result.is_none(), "DS_ENV is ignored without envs");
+
+        let flag = SelectedEnv::Flag("prod".to_string());
+        let err = match_env(BTreeMap::new(), None, Some(&flag), true, &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Environment 'prod' selected, but the command has no environments"
+        );
+
+        let result = match_env(envs_ref.clone(), None, None, true, &["dev", "extra"]).unwrap();
+        let (key, env, remaining) = result.unwrap();
+        assert_eq!(key, "dev");
         assert_eq!(env, envs.get("dev").unwrap());
         assert_eq!(remaining, ["extra"]);
 
-        let result = match_env(envs_ref.clone(), Some("prod"), &[]).unwrap();
-        let (env, remaining) = result.unwrap();
+        let result = match_env(envs_ref.clone(), Some("prod"), None, true, &[]).unwrap();
+        let (key, env, remaining) = result.unwrap();
+        assert_eq!(key, "prod");
         assert_eq!(env, envs.get("prod").unwrap());
         assert!(remaining.is_empty());
 
-        let err = match_env(envs_ref.clone(), None, &[]).unwrap_err();
+        let err = match_env(envs_ref.clone(), None, None, true, &[]).unwrap_err();
         assert!(err.to_string().contains("No environment specified"));
 
-        let err = match_env(envs_ref.clone(), Some("missing"), &[]).unwrap_err();
+        let err = match_env(envs_ref.clone(), Some("missing"), None, true, &[]).unwrap_err();
         assert!(
             err.to_string()
                 .contains("default environment 'missing' is not found")
         );
 
-        let err = match_env(envs_ref, None, &["unknown"]).unwrap_err();
+        let err = match_env(envs_ref.clone(), None, None, true, &["unknown"]).unwrap_err();
         assert!(err.to_string().contains("Environment not found"));
+
+        let result = match_env(envs_ref.clone(), None, Some(&flag), true, &["dev"]).unwrap();
+        let (key, _, remaining) = result.unwrap();
+        assert_eq!(key, "prod", "selected env wins over positional");
+        assert_eq!(remaining, ["dev"]);
+
+        let staging = SelectedEnv::Var("staging".to_string());
+        let err = match_env(envs_ref.clone(), None, Some(&staging), true, &[]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("'staging' not found, available environments: dev, prod")
+        );
+
+        let result = match_env(envs_ref.clone(), Some("prod"), None, false, &["dev"]).unwrap();
+        let (key, _, remaining) = result.unwrap();
+        assert_eq!(key, "prod", "positional matching disabled uses default");
+        assert_eq!(remaining, ["dev"]);
+
+        let err = match_env(envs_ref, None, None, false, &["dev"]).unwrap_err();
+        assert!(err.to_string().contains("No environment specified"));
+    }
+
+    #[test]
+    fn required_vars() {
+        struct Case {
+            name: &'static str,
+            var: RequiredVar,
+            value: Option<&'static str>,
+            expected_problem: Option<&'static str>,
+        }
+
+        fn typed(var_type: VarType) -> RequiredVar {
+            RequiredVar::Config(RequiredVarConfig {
+                name: "VAR".to_string(),
+                var_type: Some(var_type),
+                pattern: None,
+            })
+        }
+
+        let cases = [
+            Case {
+                name: "Name only, missing",
+                var: RequiredVar::Name("VAR".to_string()),
+                value: None,
+                expected_problem: Some("missing"),
+            },
+            Case {
+                name: "Name only, empty is fine",
+                var: RequiredVar::Name("VAR".to_string()),
+                value: Some(""),
+                expected_problem: None,
+            },
+            Case {
+                name: "Non empty, whitespace",
+                var: typed(VarType::NonEmpty),
+                value: Some("  "),
+                expected_problem: Some("expected a non-empty value"),
+            },
+            Case {
+                name: "Int, valid",
+                var: typed(VarType::Int),
+                value: Some("8080"),
+                expected_problem: None,
+            },
+            Case {
+                name: "Int, invalid",
+                var: typed(VarType::Int),
+                value: Some("80.5"),
+                expected_problem: Some("expected an integer"),
+            },
+            Case {
+                name: "Url, valid",
+                var: typed(VarType::Url),
+                value: Some("postgres://localhost:5432/db"),
+                expected_problem: None,
+            },
+            Case {
+                name: "Url, no scheme",
+                var: typed(VarType::Url),
+                value: Some("localhost:5432"),
+                expected_problem: Some("expected a URL"),
+            },
+            Case {
+                name: "Pattern, not matching",
+                var: RequiredVar::Config(RequiredVarConfig {
+                    name: "VAR".to_string(),
+                    var_type: None,
+                    pattern: Some("^ghp_".to_string()),
+                }),
+                value: Some("token"),
+                expected_problem: Some("does not match '^ghp_'"),
+            },
+        ];
+
+        for case in cases {
+            let problem = case.var.validate(case.value).unwrap();
+            assert_eq!(problem.as_deref(), case.expected_problem, "{}", case.name);
+        }
+
+        let required = [
+            RequiredVar::Name("DS_TEST_MISSING_VAR".to_string()),
+            typed(VarType::Int),
+        ];
+        let vars = [("VAR".to_string(), "abc".to_string())].into();
+
+        let err = check_required_vars(
+            &required.iter().collect::<Vec<_>>(),
+            &vars,
+            Some("prod"),
+            &[".env.prod"],
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains("in env 'prod' (.env.prod)"));
+        assert!(err.contains("DS_TEST_MISSING_VAR: missing"));
+        assert!(err.contains("VAR: expected an integer"));
+        assert!(!err.contains("abc"), "The value is not shown");
+    }
+
+    #[test]
+    fn load_env_files() {
+        let file_path = "./tests/fixtures/full.json";
+        let env: Env = serde_json::from_str(
+            r#"{ "path": ["./.env-example", "./.env-override", { "path": "./.env-missing", "optional": true }] }"#,
+        )
+        .unwrap();
+
+        let vars = env.get_env_vars(file_path).unwrap().vars.unwrap();
+        assert_eq!(vars.get("ENV_VAR").unwrap(), "override_value");
+        assert_eq!(vars.get("OVERRIDE_VAR").unwrap(), "override");
+
+        let env: Env = serde_json::from_str(r#"{ "path": "./.env-example" }"#).unwrap();
+        let vars = env.get_env_vars(file_path).unwrap().vars.unwrap();
+        assert_eq!(vars.get("ENV_VAR").unwrap(), "env_value");
+
+        let env: Env =
+            serde_json::from_str(r#"{ "path": ["./.env-example", "./.env-missing"] }"#).unwrap();
+        let err = env.get_env_vars(file_path).unwrap_err();
+        assert!(err.to_string().contains("Dotenv file not found"));
+        assert!(err.to_string().contains(".env-missing"));
+    }
+
+    #[test]
+    fn env_isolation() {
+        let mut isolation = EnvIsolation::default();
+        isolation.merge(None, None, Some(&vec!["HOME".to_string()]));
+
+        let vars = isolation.inherited_vars();
+        assert!(vars.contains_key("PATH"), "not clean keeps the shell env");
+        assert!(!vars.contains_key("HOME"), "unset vars are removed");
+
+        isolation.merge(Some(true), Some(&vec!["PATH".to_string()]), None);
+        let vars = isolation.inherited_vars();
+        assert_eq!(vars.keys().collect::<Vec<_>>(), ["PATH"]);
+
+        let mut cmd = ProcessCommand::new("sh");
+        isolation.apply(&mut cmd);
+        let envs: BTreeMap<_, _> = cmd.get_envs().collect();
+
+        assert!(envs.get(std::ffi::OsStr::new("PATH")).unwrap().is_some());
+        assert!(
+            envs.get(std::ffi::OsStr::new("HOME"))
+                .copied()
+                .flatten()
+                .is_none()
+        );
     }
 }
@@ -0,0 +1,277 @@
+use crate::{
+    config::get_state_dir,
+    dir::{Fnv, file_name, keys_file_name, path_id},
+    runner::COLOR_VARS,
+};
+use anyhow::Result;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use std::{
+    fs,
+    hash::Hasher,
+    path::{Component, Path, PathBuf},
+    process::Command as ProcessCommand,
+};
+
+/// Build a glob set, globs are relative to the root of the command
+fn glob_set(globs: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(GlobBuilder::new(glob).literal_separator(true).build()?);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Check that `inputs` and `outputs` globs stay inside the root of the command
+/// - Files are tracked relative to the root, so files outside of it can't be matched
+pub fn validate_globs<'a>(globs: impl IntoIterator<Item = &'a String>) -> Result<()> {
+    for glob in globs {
+        let outside = Path::new(glob).components().any(|component| {
+            matches!(
+                component,
+                Component::ParentDir | Component::RootDir | Component::Prefix(_)
+            )
+        });
+
+        if outside {
+            return Err(anyhow::anyhow!(
+                "`inputs` and `outputs` have to be inside the root of the command, got: {}",
+                glob
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the directory a glob starts at, the components before the first one with a wildcard
+/// - e.g. `src/**/*.graphql` is `src`, `**/*.rs` is the root itself
+fn base_dir(glob: &str) -> PathBuf {
+    Path::new(glob)
+        .components()
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '[', '{'])
+        })
+        .collect()
+}
+
+/// List the files the globs can match, relative to the root and sorted, skipping `.git`
+/// - Only walks the base directories of the globs, so e.g. `src/**` doesn't walk `node_modules`
+fn list_files(root: &Path, globs: &[&String]) -> Vec<PathBuf> {
+    let mut bases: Vec<PathBuf> = globs.iter().map(|glob| base_dir(glob)).collect();
+    bases.sort();
+    bases.dedup_by(|base, parent| base.starts_with(parent));
+
+    let mut files: Vec<PathBuf> = bases
+        .iter()
+        .filter(|base| root.join(base).exists())
+        .flat_map(|base| {
+            WalkBuilder::new(root.join(base))
+                .standard_filters(false)
+                .filter_entry(|entry| entry.file_name() != ".git")
+                .build()
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| entry.path().strip_prefix(root).ok().map(Path::to_path_buf))
+        .collect();
+
+    files.sort();
+    files
+}
+
+/// The inputs and outputs of a command, to skip it when it is up to date
+pub struct UpToDate<'a> {
+    root: &'a Path,
+    inputs: &'a [String],
+    outputs: &'a [String],
+    /// The files the inputs and outputs can match, listed once for both checks
+    files: Vec<PathBuf>,
+}
+
+impl<'a> UpToDate<'a> {
+    pub fn new(root: &'a Path, inputs: &'a [String], outputs: &'a [String]) -> Self {
+        let globs: Vec<&String> = inputs.iter().chain(outputs).collect();
+
+        UpToDate {
+            root,
+            inputs,
+            outputs,
+            files: list_files(root, &globs),
+        }
+    }
+
+    /// Hash the contents of the input files, with the resolved command and its env vars
+    /// - Runs in a terminal and piped runs get the same fingerprint
+    pub fn fingerprint(&self, cmd_str: &str, command: &ProcessCommand) -> Result<String> {
+        let inputs = glob_set(self.inputs)?;
+        let mut hasher = Fnv::default();
+
+        for file in &self.files {
+            if inputs.is_match(file) {
+                hasher.write(file.as_os_str().as_encoded_bytes());
+                hasher.write(&[0]);
+                hasher.write(&fs::read(self.root.join(file))?);
+                hasher.write(&[0]);
+            }
+        }
+
+        hasher.write(cmd_str.as_bytes());
+
+        // The color vars depend on the terminal, not on what the command does
+        let mut vars: Vec<_> = command
+            .get_envs()
+            .filter(|(key, _)| !COLOR_VARS.iter().any(|var| key == var))
+            .collect();
+        vars.sort();
+        for (key, value) in vars {
+            hasher.write(key.as_encoded_bytes());
+            hasher.write(b"=");
+            if let Some(value) = value {
+                hasher.write(value.as_encoded_bytes());
+            }
+            hasher.write(&[0]);
+        }
+
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+
+    /// Check if every output glob matches at least one file
+    pub fn outputs_exist(&self) -> Result<bool> {
+        for output in self.outputs {
+            let glob = glob_set(std::slice::from_ref(output))?;
+            if !self.files.iter().any(|file| glob.is_match(file)) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// The fingerprints of the last successful runs, per ds file in the state directory
+pub struct Fingerprints {
+    dir: PathBuf,
+}
+
+impl Fingerprints {
+    /// Open the cache of a ds file, e.g. `~/.local/state/do-something/fingerprints/<id>`
+    pub fn new(file_path: &Path) -> Result<Self> {
+        let state_dir = get_state_dir().ok_or(anyhow::anyhow!("Could not find state directory"))?;
+
+        Ok(Fingerprints {
+            dir: state_dir.join("fingerprints").join(path_id(file_path)),
+        })
+    }
+
+    /// Get the file of a command per env, so alternating envs don't overwrite each other
+    /// - e.g. `build @prod`, an `@` in the keys is encoded so it can't clash
+    fn path(&self, keys: &[String], env: Option<&str>) -> PathBuf {
+        match env {
+            Some(env) => self
+                .dir
+                .join(format!("{} @{}", keys_file_name(keys), file_name(env))),
+            None => self.dir.join(keys_file_name(keys)),
+        }
+    }
+
+    /// Get the fingerprint of the last successful run of the command with the env
+    pub fn get(&self, keys: &[String], env: Option<&str>) -> Option<String> {
+        fs::read_to_string(self.path(keys, env)).ok()
+    }
+
+    /// Save the fingerprint of a successful run
+    pub fn set(&self, keys: &[String], env: Option<&str>, fingerprint: &str) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(keys, env), fingerprint)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_inputs() {
+        let root = std::env::temp_dir().join(format!("ds-fingerprint-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/schema.graphql"), "type Query").unwrap();
+        fs::write(root.join("README.md"), "docs").unwrap();
+
+        let inputs = vec!["src/**/*.graphql".to_string()];
+        let outputs = vec!["gen/*.ts".to_string()];
+        let up_to_date = UpToDate::new(&root, &inputs, &outputs);
+        // Only the base directories of the globs are walked
+        assert_eq!(up_to_date.files, vec![PathBuf::from("src/schema.graphql")]);
+
+        let command = ProcessCommand::new("codegen");
+        let fingerprint = up_to_date.fingerprint("codegen", &command).unwrap();
+        assert!(!up_to_date.outputs_exist().unwrap());
+
+        fs::create_dir_all(root.join("gen")).unwrap();
+        fs::write(root.join("gen/schema.ts"), "export {}").unwrap();
+        let up_to_date = UpToDate::new(&root, &inputs, &outputs);
+        assert!(up_to_date.outputs_exist().unwrap());
+
+        // Files that are not inputs don't change the fingerprint
+        fs::write(root.join("README.md"), "more docs").unwrap();
+        assert_eq!(
+            up_to_date.fingerprint("codegen", &command).unwrap(),
+            fingerprint
+        );
+
+        // The command and its env vars do
+        assert_ne!(
+            up_to_date.fingerprint("codegen --watch", &command).unwrap(),
+            fingerprint
+        );
+        let mut with_env = ProcessCommand::new("codegen");
+        with_env.env("TARGET", "prod");
+        assert_ne!(
+            up_to_date.fingerprint("codegen", &with_env).unwrap(),
+            fingerprint
+        );
+
+        // Except for the color vars, only set when running in a terminal
+        let mut with_colors = ProcessCommand::new("codegen");
+        for var in COLOR_VARS {
+            with_colors.env(var, "1");
+        }
+        assert_eq!(
+            up_to_date.fingerprint("codegen", &with_colors).unwrap(),
+            fingerprint
+        );
+
+        fs::write(root.join("src/schema.graphql"), "type Mutation").unwrap();
+        assert_ne!(
+            up_to_date.fingerprint("codegen", &command).unwrap(),
+            fingerprint
+        );
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn fingerprint_per_env() {
+        let fingerprints = Fingerprints {
+            dir: PathBuf::from("/state"),
+        };
+        let keys = vec!["build".to_string()];
+
+        assert_eq!(fingerprints.path(&keys, None), Path::new("/state/build"));
+        assert_eq!(
+            fingerprints.path(&keys, Some("prod")),
+            Path::new("/state/build @prod")
+        );
+        assert_ne!(
+            fingerprints.path(&["build@prod".to_string()], None),
+            fingerprints.path(&keys, Some("prod"))
+        );
+    }
+}
@@ -1,7 +1,11 @@
 use crate::{
-    command::{Command, RootConfig},
-    env::Env,
+    cli::BUILTIN_COMMANDS,
+    command::{Command, Cwd, RetryConfig, RootConfig, Shell, WhenConfig},
+    config::{ConfigDuration, one_or_many},
+    env::{Env, RequiredVar, validate_required_vars},
     help::HelpRow,
+    lock::LockSetting,
+    matrix::Matrix,
 };
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -59,10 +63,51 @@ pub struct Group {
     pub default_env: Option<String>,
     /// Optional root configuration, to define where the group is run from.
     pub root: Option<RootConfig>,
+    /// Optionally run the commands from another directory than the root, e.g. `invocation`.
+    pub cwd: Option<Cwd>,
     /// Optional group mode, to define if it is namespaced or flattened.
     pub mode: Option<GroupMode>,
     /// Optional aliases for the group, used to run it with different names.
     pub aliases: Option<Vec<String>>,
+    /// Optional variables that must be set before any command in the group is run.
+    pub required_vars: Option<Vec<RequiredVar>>,
+    /// Optional flag to disable matching the environment from the first extra argument.
+    /// If disabled, the env can only be selected with `--env`, `DS_ENV` or `default_env`.
+    pub positional_envs: Option<bool>,
+    /// Optional shell to run the command strings in the group with.
+    pub shell: Option<Shell>,
+    /// Optional time to wait for the process group to exit after SIGTERM, before it is killed.
+    pub grace_period: Option<ConfigDuration>,
+    /// Optional time after which the commands in the group are terminated.
+    pub timeout: Option<ConfigDuration>,
+    /// Optional retry configuration, to retry the commands in the group when they fail.
+    pub retry: Option<RetryConfig>,
+    /// Optional globs, relative to the root, of the files that re-run the commands in watch mode.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub watch: Option<Vec<String>>,
+    /// Optionally save the output of every run of the commands in the group to a log file.
+    pub log: Option<bool>,
+    /// Optional commands to run before each command in the group, it is skipped if one of them fails.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub before: Option<Vec<String>>,
+    /// Optional commands to run after each command in the group, also when it failed.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub after: Option<Vec<String>>,
+    /// Optional commands to run when a command in the group failed.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_failure: Option<Vec<String>>,
+    /// Optionally ask for confirmation before running any command in the group.
+    pub confirm: Option<bool>,
+    /// Optional conditions for the group and its commands to be available.
+    pub when: Option<WhenConfig>,
+    /// Optionally mark all commands in the group as services.
+    pub service: Option<bool>,
+    /// Optional values to expand the group into a copy for each of them, e.g. `["api", "web"]`.
+    /// The values are substituted as `{{item}}` (or `{{key}}` for named keys) in its name and commands.
+    #[serde(alias = "for_each")]
+    pub matrix: Option<Matrix>,
+    /// Optionally take a lock before running any command in the group, e.g. a named lock shared by them.
+    pub lock: Option<LockSetting>,
 }
 
 impl Group {
@@ -218,6 +263,67 @@ impl Group {
         err.map_or(Ok(rows), Err)
     }
 
+    /// Check if the group, or any command or group in it, has a matrix to expand
+    pub fn has_matrix(&self) -> bool {
+        self.matrix.is_some()
+            || self.commands.values().any(|command| match command {
+                Command::Inline(_) => false,
+                Command::Config(config) => config.matrix.is_some(),
+                Command::Group(group) => group.has_matrix(),
+            })
+    }
+
+    /// Check that every command in the group and its subgroups has something to run
+    /// - An object without `command`, `script` or `commands`, e.g. with a typo like `"comand"`,
+    ///   would otherwise be an empty command
+    /// - Checks the patterns of the required vars, so a typo in a regex is found before running
+    pub fn validate(&self) -> Result<()> {
+        validate_required_vars(self.required_vars.as_ref(), self.envs.as_ref())?;
+
+        let mut error = None;
+
+        self.walk_commands(&mut |keys, command, _| {
+            let result = match command {
+                Command::Inline(_) => Ok(()),
+                Command::Config(config) => config.validate().and_then(|_| {
+                    validate_required_vars(config.required_vars.as_ref(), config.envs.as_ref())
+                }),
+                Command::Group(group) => {
+                    validate_required_vars(group.required_vars.as_ref(), group.envs.as_ref())
+                }
+            };
+
+            if let Err(e) = result {
+                error = Some(e.context(format!("Invalid command '{}'", keys.join(" "))));
+                return Walk::Stop;
+            }
+            Walk::Continue
+        });
+
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Get the top level commands with a name or alias of a built-in command, with that name
+    /// - The built-in runs instead, the command can still be run with its other aliases or
+    ///   from the TUI
+    pub fn shadowed_commands(&self) -> Vec<(String, &'static str)> {
+        let mut shadowed = Vec::new();
+
+        self.walk_commands(&mut |keys, command, parents| {
+            // Commands of flattened groups are on the top level as well
+            if let [names] = command.resolve_aliases(keys, parents).as_slice()
+                && let Some(name) = BUILTIN_COMMANDS
+                    .iter()
+                    .find(|builtin| names.contains(builtin))
+            {
+                shadowed.push((keys.join(" "), *name));
+            }
+            Walk::Continue
+        });
+
+        shadowed
+    }
+
     /// Get the default command for the group, if it exists
     pub fn default_command<'a>(
         &'a self,
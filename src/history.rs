@@ -0,0 +1,331 @@
+use crate::config::get_state_dir;
+use anyhow::Result;
+use crossterm::style::Stylize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Maximum number of entries to keep in the history, the oldest ones are removed
+const MAX_ENTRIES: usize = 1000;
+
+/// Serialize a duration as a number of milliseconds, e.g. for scripts reading `ds history --json`
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+/// Deserialize a number of milliseconds
+fn deserialize_millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+}
+
+/// A single invocation of a command
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// When the command was started, as RFC 3339 in UTC
+    pub timestamp: String,
+    /// The directory ds was run from
+    pub cwd: PathBuf,
+    /// The ds file the command was defined in
+    pub file_path: PathBuf,
+    /// The path of keys to the command in the ds file
+    pub keys: Vec<String>,
+    /// The env the command was run with, if any
+    pub env: Option<String>,
+    /// The extra args passed on to the command
+    pub args: Vec<String>,
+    /// The exit code of the command, e.g. `130` when it was interrupted
+    pub exit_code: i32,
+    /// How long the command ran
+    #[serde(
+        rename = "duration_ms",
+        serialize_with = "serialize_millis",
+        deserialize_with = "deserialize_millis"
+    )]
+    pub duration: Duration,
+}
+
+impl HistoryEntry {
+    /// Create an entry for a command that was started at `started`
+    pub fn new(
+        started: SystemTime,
+        cwd: &Path,
+        file_path: &Path,
+        keys: &[String],
+        env: Option<&str>,
+        args: &[String],
+        exit_code: i32,
+    ) -> Self {
+        HistoryEntry {
+            timestamp: humantime::format_rfc3339_seconds(started).to_string(),
+            cwd: cwd.to_path_buf(),
+            file_path: file_path.to_path_buf(),
+            keys: keys.to_vec(),
+            env: env.map(str::to_string),
+            args: args.to_vec(),
+            exit_code,
+            duration: Duration::from_millis(
+                started.elapsed().unwrap_or_default().as_millis() as u64
+            ),
+        }
+    }
+
+    /// The invocation as it could be typed, e.g. `api build prod --release`
+    pub fn invocation(&self) -> String {
+        self.keys
+            .iter()
+            .chain(self.env.iter())
+            .chain(self.args.iter())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Check if all filter words are found in the invocation
+    pub fn matches(&self, filter: &[&str]) -> bool {
+        let invocation = self.invocation();
+        filter.iter().all(|word| invocation.contains(word))
+    }
+}
+
+/// The run history, stored as JSON lines in the state directory
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    /// Open the history in the state directory, e.g. `~/.local/state/do-something/history.jsonl`
+    pub fn open() -> Result<Self> {
+        let state_dir = get_state_dir().ok_or(anyhow::anyhow!("Could not find state directory"))?;
+
+        Ok(History {
+            path: state_dir.join("history.jsonl"),
+        })
+    }
+
+    /// Load all entries, oldest first
+    /// - Lines that can't be parsed (e.g. from an older version) are skipped
+    pub fn load(&self) -> Result<Vec<HistoryEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&self.path)?;
+        let entries = content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// Take an exclusive lock on the history, released when the returned file is closed
+    /// - Other ds processes wait, so they don't append to a file that is being replaced
+    fn lock(&self) -> Result<File> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.path.with_extension("jsonl.lock"))?;
+
+        // SAFETY: Only locks the file we opened, the lock is released when it is closed
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(file)
+    }
+
+    /// Add an entry, removing the oldest ones if the history is full
+    pub fn record(&self, entry: HistoryEntry) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let _lock = self.lock()?;
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())?;
+
+        let content = fs::read_to_string(&self.path)?;
+        let lines: Vec<&str> = content.lines().collect();
+        if lines.len() <= MAX_ENTRIES {
+            return Ok(());
+        }
+
+        // Write to a temporary file first, so the history is never half written
+        let mut trimmed = lines[lines.len() - MAX_ENTRIES..].join("\n");
+        trimmed.push('\n');
+        let tmp = self
+            .path
+            .with_extension(format!("jsonl.{}.tmp", std::process::id()));
+        fs::write(&tmp, trimmed)?;
+        fs::rename(&tmp, &self.path)?;
+
+        Ok(())
+    }
+
+    /// Get an entry by its id, 1 being the most recent one
+    pub fn get(&self, id: usize) -> Result<HistoryEntry> {
+        let entries = self.load()?;
+
+        id.checked_sub(1)
+            .and_then(|index| entries.into_iter().rev().nth(index))
+            .ok_or_else(|| match id {
+                1 => anyhow::anyhow!("No commands in the history yet"),
+                _ => anyhow::anyhow!("History entry {} not found", id),
+            })
+    }
+}
+
+/// Print the entries matching the filter, most recent first with their id
+/// - As a table, or as a JSON array
+pub fn print_history(
+    entries: &[HistoryEntry],
+    filter: &[&str],
+    limit: usize,
+    json: bool,
+) -> Result<()> {
+    let matching: Vec<(usize, &HistoryEntry)> = entries
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, entry)| (index + 1, entry))
+        .filter(|(_, entry)| entry.matches(filter))
+        .take(limit)
+        .collect();
+
+    if json {
+        #[derive(Serialize)]
+        struct Numbered<'a> {
+            id: usize,
+            #[serde(flatten)]
+            entry: &'a HistoryEntry,
+        }
+
+        let numbered: Vec<Numbered> = matching
+            .into_iter()
+            .map(|(id, entry)| Numbered { id, entry })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&numbered)?);
+        return Ok(());
+    }
+
+    let width = matching
+        .iter()
+        .map(|(_, entry)| entry.invocation().len())
+        .max()
+        .unwrap_or(0);
+
+    for (id, entry) in matching {
+        let exit_code = match entry.exit_code {
+            0 => format!("{:>3}", 0).green(),
+            code => format!("{:>3}", code).red(),
+        };
+
+        println!(
+            "{:>4}  {}  {:width$}  {}  {:>10}  {}",
+            id,
+            entry.timestamp.as_str().dim(),
+            entry.invocation(),
+            exit_code,
+            humantime::format_duration(entry.duration).to_string(),
+            entry.cwd.display().to_string().dim(),
+            width = width,
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(keys: &[&str], env: Option<&str>, args: &[&str]) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: "2026-01-01T00:00:00Z".to_string(),
+            cwd: PathBuf::from("/project"),
+            file_path: PathBuf::from("/project/ds.json"),
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            env: env.map(str::to_string),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            exit_code: 0,
+            duration: Duration::from_millis(1500),
+        }
+    }
+
+    #[test]
+    fn filter_entries() {
+        struct Case {
+            name: &'static str,
+            filter: Vec<&'static str>,
+            expected: bool,
+        }
+
+        let cases = [
+            Case {
+                name: "No filter",
+                filter: vec![],
+                expected: true,
+            },
+            Case {
+                name: "Key and env",
+                filter: vec!["build", "prod"],
+                expected: true,
+            },
+            Case {
+                name: "Partial arg",
+                filter: vec!["--rel"],
+                expected: true,
+            },
+            Case {
+                name: "Missing word",
+                filter: vec!["build", "dev"],
+                expected: false,
+            },
+        ];
+
+        let entry = entry(&["api", "build"], Some("prod"), &["--release"]);
+        assert_eq!(entry.invocation(), "api build prod --release");
+
+        for case in cases {
+            assert_eq!(entry.matches(&case.filter), case.expected, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn record_and_get() {
+        let dir = std::env::temp_dir().join(format!("ds-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let history = History {
+            path: dir.join("history.jsonl"),
+        };
+
+        assert!(history.get(1).is_err());
+
+        history.record(entry(&["build"], None, &[])).unwrap();
+        history
+            .record(entry(&["test"], Some("ci"), &["-x"]))
+            .unwrap();
+
+        assert_eq!(history.load().unwrap().len(), 2);
+        let line = fs::read_to_string(&history.path).unwrap();
+        assert!(line.contains(r#""duration_ms":1500"#), "{}", line);
+        assert_eq!(history.get(1).unwrap().keys, ["test"]);
+        assert_eq!(history.get(2).unwrap().keys, ["build"]);
+        assert!(history.get(3).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{
+    command::RetryConfig,
+    process::{ProcessOptions, ProcessOutcome},
+    runner::run_with_retry,
+};
+use anyhow::Result;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{Command as ProcessCommand, Stdio},
+    thread::{self, JoinHandle},
+};
+
+/// Copy the output line by line to the target, prefixing every line with the label
+/// - Lines are written at once, so the output of commands running in parallel doesn't interleave
+fn copy_labelled(source: impl Read, mut target: impl Write, label: &str) {
+    let mut reader = BufReader::new(source);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                if !line.ends_with(b"\n") {
+                    line.push(b'\n');
+                }
+
+                let mut labelled = Vec::with_capacity(label.len() + line.len() + 1);
+                labelled.extend_from_slice(label.as_bytes());
+                labelled.push(b' ');
+                labelled.extend_from_slice(&line);
+                let _ = target.write_all(&labelled);
+                let _ = target.flush();
+            }
+        }
+    }
+}
+
+fn spawn_labelled(
+    source: impl Read + Send + 'static,
+    target: impl Write + Send + 'static,
+    label: &str,
+) -> JoinHandle<()> {
+    let label = label.to_string();
+    thread::spawn(move || copy_labelled(source, target, &label))
+}
+
+/// Run the command like `run_with_retry`, prefixing every line of its output with the label
+/// - The command doesn't get the terminal or stdin, as other commands run at the same time
+pub fn run_labelled(
+    cmd: &mut ProcessCommand,
+    label: &str,
+    options: ProcessOptions,
+    retry: Option<&RetryConfig>,
+) -> Result<ProcessOutcome> {
+    let (stdout, stdout_writer) = io::pipe()?;
+    let (stderr, stderr_writer) = io::pipe()?;
+    cmd.stdin(Stdio::null());
+    cmd.stdout(stdout_writer);
+    cmd.stderr(stderr_writer);
+
+    let readers = [
+        spawn_labelled(stdout, io::stdout(), label),
+        spawn_labelled(stderr, io::stderr(), label),
+    ];
+
+    let options = ProcessOptions {
+        background: true,
+        ..options
+    };
+    let outcome = run_with_retry(cmd, options, retry);
+
+    // Close our end of the pipes, so the readers get to the end of the output
+    cmd.stdout(Stdio::inherit());
+    cmd.stderr(Stdio::inherit());
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_lines() {
+        let mut output = Vec::new();
+        copy_labelled("building\n\ndone".as_bytes(), &mut output, "[prod]");
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[prod] building\n[prod] \n[prod] done\n"
+        );
+    }
+}
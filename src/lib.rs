@@ -8,14 +8,31 @@
 //! - Command grouping and aliases
 //! - Flexible scoping (global, git-root, exact)
 //!
+//! Only supports Unix-like systems, as running commands relies on process groups and signals.
 
+#[cfg(not(unix))]
+compile_error!("do-something only supports Unix-like systems, e.g. Linux and macOS");
+
+pub mod cli;
 pub mod command;
 pub mod config;
+pub mod confirm;
 pub mod dir;
 pub mod do_something;
 pub mod ds_file;
 pub mod env;
+pub mod fingerprint;
 pub mod group;
 pub mod help;
+pub mod history;
+pub mod labelled;
+pub mod lock;
+pub mod logs;
+pub mod matrix;
+pub mod process;
 pub mod runner;
+pub mod service;
+pub mod summary;
 pub mod tui;
+pub mod wait;
+pub mod watch;
@@ -0,0 +1,236 @@
+use crate::{
+    config::{ConfigDuration, get_state_dir},
+    dir::{file_name, keys_file_name, path_id},
+};
+use anyhow::Result;
+use crossterm::style::Stylize;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Interval between attempts to take a lock that is held by another run
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Which runs exclude each other
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LockScope {
+    /// Runs of the same command
+    #[default]
+    Key,
+    /// Runs of any locked command in the same ds file
+    Project,
+}
+
+/// Configuration of a lock, to prevent the command from running more than once at the same time
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct LockConfig {
+    /// Optional scope of the lock, `key` (default) or `project`.
+    pub scope: Option<LockScope>,
+    /// Optional name of a lock shared by several commands, e.g. `db`. Overrides the scope.
+    pub name: Option<String>,
+    /// Optionally wait for the lock if it is held, instead of failing right away. Defaults to true.
+    pub wait: Option<bool>,
+    /// Optional time to give up waiting for the lock after.
+    pub timeout: Option<ConfigDuration>,
+}
+
+/// The lock setting of a command or group
+/// - `true` locks per command, a string is the name of a shared lock, `false` disables a lock of a parent
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum LockSetting {
+    Enabled(bool),
+    Name(String),
+    Config(LockConfig),
+}
+
+impl LockSetting {
+    /// Get the lock configuration, or None if locking is disabled
+    pub fn config(&self) -> Option<LockConfig> {
+        match self {
+            LockSetting::Enabled(false) => None,
+            LockSetting::Enabled(true) => Some(LockConfig::default()),
+            LockSetting::Name(name) => Some(LockConfig {
+                name: Some(name.clone()),
+                ..LockConfig::default()
+            }),
+            LockSetting::Config(config) => Some(config.clone()),
+        }
+    }
+}
+
+impl LockConfig {
+    /// Get the lock file of a run, in the state directory per ds file
+    /// e.g. `~/.local/state/do-something/locks/<id>/keys/db migrate.lock`
+    fn path(&self, file_path: &Path, keys: &[String]) -> Result<PathBuf> {
+        let state_dir = get_state_dir().ok_or(anyhow::anyhow!("Could not find state directory"))?;
+        let dir = state_dir.join("locks").join(path_id(file_path));
+
+        Ok(match (&self.name, self.scope.unwrap_or_default()) {
+            (Some(name), _) => dir.join("names").join(format!("{}.lock", file_name(name))),
+            (None, LockScope::Key) => dir
+                .join("keys")
+                .join(format!("{}.lock", keys_file_name(keys))),
+            (None, LockScope::Project) => dir.join("project.lock"),
+        })
+    }
+
+    /// Describe the lock for messages, e.g. `lock 'db'`
+    fn describe(&self, keys: &[String]) -> String {
+        match (&self.name, self.scope.unwrap_or_default()) {
+            (Some(name), _) => format!("lock '{}'", name),
+            (None, LockScope::Key) => format!("lock of '{}'", keys.join(" ")),
+            (None, LockScope::Project) => "project lock".to_string(),
+        }
+    }
+
+    /// Take the lock, waiting for other runs to release it if configured
+    /// - The lock is released when the returned guard is dropped, or when ds exits
+    pub fn acquire(&self, file_path: &Path, keys: &[String]) -> Result<RunLock> {
+        let path = self.path(file_path, keys)?;
+        let start = Instant::now();
+        let mut waiting = false;
+
+        loop {
+            match RunLock::try_lock(&path)? {
+                Ok(lock) => return Ok(lock),
+                Err(holder) => {
+                    let held_by = match holder {
+                        Some(pid) => format!("held by pid {}", pid),
+                        None => "held by another process".to_string(),
+                    };
+
+                    if !self.wait.unwrap_or(true) {
+                        return Err(anyhow::anyhow!(
+                            "The {} is {}",
+                            self.describe(keys),
+                            held_by
+                        ));
+                    }
+
+                    if let Some(timeout) = self.timeout
+                        && start.elapsed() >= timeout.0
+                    {
+                        return Err(anyhow::anyhow!(
+                            "Timed out after {} waiting for the {}, {}",
+                            humantime::format_duration(timeout.0),
+                            self.describe(keys),
+                            held_by
+                        ));
+                    }
+
+                    if !waiting {
+                        waiting = true;
+                        eprintln!(
+                            "{}",
+                            format!("Waiting for the {}, {}", self.describe(keys), held_by).dim()
+                        );
+                    }
+
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+        }
+    }
+}
+
+/// An advisory lock on a file, holding the pid of the run that took it
+#[derive(Debug)]
+pub struct RunLock {
+    file: File,
+}
+
+impl RunLock {
+    /// Try to take the lock once
+    /// - Returns the pid of the process holding the lock, if it is taken
+    fn try_lock(path: &Path) -> Result<std::result::Result<RunLock, Option<u32>>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        // SAFETY: Only locks the file we opened, the lock is released when it is closed
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err.into());
+            }
+
+            let mut pid = String::new();
+            file.read_to_string(&mut pid)?;
+            return Ok(Err(pid.trim().parse().ok()));
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(std::process::id().to_string().as_bytes())?;
+        file.flush()?;
+
+        Ok(Ok(RunLock { file }))
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // Clear the pid, so it isn't shown for a lock that is no longer held
+        let _ = self.file.set_len(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_lock_settings() {
+        let setting: LockSetting = serde_json::from_str("true").unwrap();
+        assert_eq!(setting.config(), Some(LockConfig::default()));
+
+        let setting: LockSetting = serde_json::from_str("false").unwrap();
+        assert_eq!(setting.config(), None);
+
+        let setting: LockSetting = serde_json::from_str(r#""db""#).unwrap();
+        assert_eq!(setting.config().unwrap().name.as_deref(), Some("db"));
+
+        let setting: LockSetting =
+            serde_json::from_str(r#"{"scope": "project", "wait": false, "timeout": "5s"}"#)
+                .unwrap();
+        let config = setting.config().unwrap();
+        assert_eq!(config.scope, Some(LockScope::Project));
+        assert_eq!(config.wait, Some(false));
+        assert_eq!(config.timeout, Some(ConfigDuration(Duration::from_secs(5))));
+    }
+
+    #[test]
+    fn lock_excludes_other_runs() {
+        let path = std::env::temp_dir().join(format!("ds-lock-{}/db.lock", std::process::id()));
+
+        let lock = RunLock::try_lock(&path).unwrap().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+
+        // A lock is per open file, so a second open file conflicts like another process would
+        let holder = RunLock::try_lock(&path).unwrap().unwrap_err();
+        assert_eq!(holder, Some(std::process::id()));
+
+        drop(lock);
+        assert!(RunLock::try_lock(&path).unwrap().is_ok());
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,292 @@
+use crate::{
+    command::RetryConfig,
+    config::get_state_dir,
+    dir::{keys_file_name, path_id},
+    process::{ProcessOptions, ProcessOutcome},
+    runner::run_with_retry,
+};
+use anyhow::Result;
+use crossterm::style::Stylize;
+use std::{
+    fs::{self, File},
+    io::{self, IsTerminal, Read, Write},
+    os::fd::{AsRawFd, FromRawFd},
+    path::{Path, PathBuf},
+    process::{Command as ProcessCommand, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+/// Default number of log files to keep per command
+pub const DEFAULT_MAX_LOGS: usize = 10;
+
+/// Interval to check for new output when following a log
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Where to save the output of the runs of a command
+#[derive(Debug, Clone)]
+pub struct RunLog {
+    pub dir: PathBuf,
+    /// How many log files to keep, the oldest ones are removed
+    pub max_logs: usize,
+}
+
+impl RunLog {
+    /// Get the log directory of a command, by its ds file and keys
+    /// - Logs are stored in the state directory, e.g. `~/.local/state/do-something/logs`
+    pub fn new(file_path: &Path, keys: &[String], max_logs: usize) -> Result<Self> {
+        let state_dir = get_state_dir().ok_or(anyhow::anyhow!("Could not find state directory"))?;
+
+        let dir = state_dir
+            .join("logs")
+            .join(path_id(file_path))
+            .join(keys_file_name(keys));
+
+        Ok(RunLog { dir, max_logs })
+    }
+
+    /// Create a new timestamped log file, removing the oldest ones
+    pub fn create(&self) -> Result<File> {
+        fs::create_dir_all(&self.dir)?;
+
+        // Timestamps sort chronologically, colons are replaced to keep the name portable
+        let timestamp = humantime::format_rfc3339_millis(SystemTime::now())
+            .to_string()
+            .replace(':', "-");
+        let file = File::create(self.dir.join(format!("{}.log", timestamp)))?;
+
+        let logs = list_logs(&self.dir)?;
+        let excess = logs.len().saturating_sub(self.max_logs.max(1));
+        for log in &logs[..excess] {
+            let _ = fs::remove_file(log);
+        }
+
+        Ok(file)
+    }
+
+    /// Get the latest log file, if any
+    pub fn latest(&self) -> Result<Option<PathBuf>> {
+        if !self.dir.is_dir() {
+            return Ok(None);
+        }
+
+        Ok(list_logs(&self.dir)?.pop())
+    }
+}
+
+/// List the log files in a directory, oldest first
+fn list_logs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut logs = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "log") {
+            logs.push(path);
+        }
+    }
+
+    logs.sort();
+    Ok(logs)
+}
+
+/// Open a pseudo terminal with the size of our own terminal, returning the master and slave
+fn open_pty() -> Result<(File, File)> {
+    let mut master = 0;
+    let mut slave = 0;
+
+    // SAFETY: The pointers are valid for the duration of the calls, and the opened fds are owned by the files
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        let size_ptr = if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 {
+            &mut size as *mut libc::winsize
+        } else {
+            std::ptr::null_mut()
+        };
+
+        if libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            size_ptr,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let master = File::from_raw_fd(master);
+        let slave = File::from_raw_fd(slave);
+
+        // Don't leak the pty into other commands
+        libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(slave.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+
+        // Keep newlines as they are, our own terminal already translates them
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == 0 {
+            termios.c_oflag &= !libc::ONLCR;
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+        }
+
+        Ok((master, slave))
+    }
+}
+
+/// Copy everything from the source to the target and the log, until the source is closed
+fn tee(
+    mut source: impl Read + Send + 'static,
+    mut target: impl Write + Send + 'static,
+    log: Arc<Mutex<File>>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0; 8192];
+
+        loop {
+            match source.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let _ = target.write_all(&buf[..n]);
+                    let _ = target.flush();
+                    let _ = log
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .write_all(&buf[..n]);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // A pty returns an error instead of EOF once the command is done
+                Err(_) => break,
+            }
+        }
+    })
+}
+
+/// Captures the output of a command, while still showing it
+/// - If stdout is a terminal, the command gets a pseudo terminal, so it keeps its colors
+/// - Otherwise stdout and stderr are piped separately
+struct LogCapture {
+    readers: Vec<JoinHandle<()>>,
+}
+
+impl LogCapture {
+    fn start(cmd: &mut ProcessCommand, file: File) -> Result<Self> {
+        let log = Arc::new(Mutex::new(file));
+
+        let readers = if io::stdout().is_terminal() {
+            let (master, slave) = open_pty()?;
+            cmd.stdout(slave.try_clone()?);
+            cmd.stderr(slave);
+            vec![tee(master, io::stdout(), log)]
+        } else {
+            let (stdout, stdout_writer) = io::pipe()?;
+            let (stderr, stderr_writer) = io::pipe()?;
+            cmd.stdout(stdout_writer);
+            cmd.stderr(stderr_writer);
+            vec![
+                tee(stdout, io::stdout(), log.clone()),
+                tee(stderr, io::stderr(), log),
+            ]
+        };
+
+        Ok(LogCapture { readers })
+    }
+
+    /// Close our end of the output, and wait for the remaining output to be copied
+    fn finish(self, cmd: &mut ProcessCommand) {
+        cmd.stdout(Stdio::inherit());
+        cmd.stderr(Stdio::inherit());
+
+        for reader in self.readers {
+            let _ = reader.join();
+        }
+    }
+}
+
+/// Run the command like `run_with_retry`, saving the output of all attempts to a new log file
+pub fn run_logged(
+    cmd: &mut ProcessCommand,
+    options: ProcessOptions,
+    retry: Option<&RetryConfig>,
+    log: Option<&RunLog>,
+) -> Result<ProcessOutcome> {
+    let Some(log) = log else {
+        return run_with_retry(cmd, options, retry);
+    };
+
+    let capture = LogCapture::start(cmd, log.create()?)?;
+    let outcome = run_with_retry(cmd, options, retry);
+    capture.finish(cmd);
+
+    outcome
+}
+
+/// Print a log file, and optionally keep printing new output
+/// - When following, it switches to newer log files of the command as they are created
+pub fn print_log(log: &RunLog, follow: bool) -> Result<()> {
+    let Some(mut path) = log.latest()? else {
+        return Err(anyhow::anyhow!(
+            "No logs found, enable them with \"log\": true"
+        ));
+    };
+
+    let mut file = File::open(&path)?;
+    let mut stdout = io::stdout();
+    io::copy(&mut file, &mut stdout)?;
+
+    if !follow {
+        return Ok(());
+    }
+
+    // Follow until ds is stopped, e.g. with Ctrl+C
+    loop {
+        thread::sleep(FOLLOW_INTERVAL);
+        io::copy(&mut file, &mut stdout)?;
+        stdout.flush()?;
+
+        if let Some(latest) = log.latest()?
+            && latest != path
+        {
+            println!("{}", format!("==> {} <==", latest.display()).dim());
+            file = File::open(&latest)?;
+            path = latest;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_output() {
+        let dir = std::env::temp_dir().join(format!("ds-logs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let log = RunLog {
+            dir: dir.clone(),
+            max_logs: 2,
+        };
+
+        for i in 0..3 {
+            let mut cmd = ProcessCommand::new("sh");
+            cmd.arg("-c")
+                .arg(format!("echo out {}; echo err {} >&2", i, i));
+
+            let outcome =
+                run_logged(&mut cmd, ProcessOptions::default(), None, Some(&log)).unwrap();
+            assert_eq!(outcome.exit_code(), 0);
+
+            // Make sure the next log gets a later timestamp
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let logs = list_logs(&dir).unwrap();
+        assert_eq!(logs.len(), 2, "Keeps the latest logs only");
+
+        let latest = fs::read_to_string(log.latest().unwrap().unwrap()).unwrap();
+        assert!(latest.contains("out 2"));
+        assert!(latest.contains("err 2"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
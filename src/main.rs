@@ -1,13 +1,16 @@
 use anyhow::Result;
-use do_something::do_something::DoSomething;
+use do_something::{cli::CliOptions, do_something::DoSomething};
 use std::env;
 
 /// Run the CLI application
 pub fn run() -> Result<()> {
-    let mut ds = DoSomething::new()?;
-
     // Get the command line arguments, skipping the first one (the program name)
     let args: Vec<String> = env::args().skip(1).collect();
+
+    // Parse the ds options, that come before the command keys
+    let (options, args) = CliOptions::parse(&args)?;
+    let mut ds = DoSomething::new(options)?;
+
     let args_str: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
 
     if args_str.is_empty() {
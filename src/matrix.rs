@@ -0,0 +1,306 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// The values to expand a command or group with, one copy for each of them
+/// - A list of items is substituted as `{{item}}`
+/// - Named keys are substituted as `{{key}}`, with a copy for every combination of their values
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Matrix {
+    Items(Vec<String>),
+    Keys(BTreeMap<String, Vec<String>>),
+}
+
+impl Matrix {
+    /// Get the first key without values, a list of items is the `item` key
+    fn empty_key(&self) -> Option<&str> {
+        match self {
+            Matrix::Items(items) => items.is_empty().then_some("item"),
+            Matrix::Keys(keys) => keys
+                .iter()
+                .find(|(_, values)| values.is_empty())
+                .map(|(key, _)| key.as_str()),
+        }
+    }
+
+    /// Get the values to substitute for every copy, e.g. `[{item: api}, {item: web}]`
+    pub fn combinations(&self) -> Vec<BTreeMap<String, String>> {
+        match self {
+            Matrix::Items(items) => items
+                .iter()
+                .map(|item| BTreeMap::from([("item".to_string(), item.clone())]))
+                .collect(),
+            Matrix::Keys(keys) => {
+                let mut combinations = vec![BTreeMap::new()];
+
+                for (key, values) in keys {
+                    combinations = combinations
+                        .into_iter()
+                        .flat_map(|combination| {
+                            values.iter().map(move |value| {
+                                let mut combination = combination.clone();
+                                combination.insert(key.clone(), value.clone());
+                                combination
+                            })
+                        })
+                        .collect();
+                }
+
+                combinations
+            }
+        }
+    }
+}
+
+/// Replace the `{{key}}` placeholders in a string
+fn substitute_str(text: &str, values: &BTreeMap<String, String>) -> String {
+    values.iter().fold(text.to_string(), |text, (key, value)| {
+        text.replace(&format!("{{{{{}}}}}", key), value)
+    })
+}
+
+/// Replace the placeholders in all strings and object keys of a value
+fn substitute(value: &mut Value, values: &BTreeMap<String, String>) {
+    match value {
+        Value::String(text) => *text = substitute_str(text, values),
+        Value::Array(items) => items.iter_mut().for_each(|item| substitute(item, values)),
+        Value::Object(object) => {
+            *object = std::mem::take(object)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    substitute(&mut value, values);
+                    (substitute_str(&key, values), value)
+                })
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+/// Expand the commands and groups of a ds file with a `matrix` (or `for_each`) into a copy for each value
+/// - Works on the JSON of the file, so the values end up in every setting, e.g. the command, root and env vars
+/// - The file itself can't have a matrix, as there is nothing to copy it into
+pub fn expand(file: &mut Value) -> Result<()> {
+    if let Some(key) = ["matrix", "for_each"]
+        .into_iter()
+        .find(|key| file.get(key).is_some())
+    {
+        return Err(anyhow::anyhow!(
+            "A ds file can't have a `{}`, add it to one of its commands or groups instead",
+            key
+        ));
+    }
+
+    expand_commands(file)
+}
+
+/// Expand the commands and groups of a group into a copy for each value of their matrix
+/// - Nested groups are expanded after their parent, so they can use the values of the parent
+fn expand_commands(group: &mut Value) -> Result<()> {
+    let Some(Value::Object(commands)) = group.get_mut("commands") else {
+        return Ok(());
+    };
+
+    let mut expanded = Map::new();
+    for (key, mut command) in std::mem::take(commands) {
+        let matrix = command.as_object_mut().and_then(|object| {
+            object
+                .remove("matrix")
+                .or_else(|| object.remove("for_each"))
+        });
+
+        let Some(matrix) = matrix else {
+            expand_commands(&mut command)?;
+            insert(&mut expanded, key, command)?;
+            continue;
+        };
+
+        let matrix: Matrix = serde_json::from_value(matrix).map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid matrix for '{}', expected a list or an object of lists: {}",
+                key,
+                e
+            )
+        })?;
+
+        // Without values there are no copies, which would remove the command without a word
+        if let Some(empty) = matrix.empty_key() {
+            return Err(anyhow::anyhow!(
+                "The matrix of '{}' has no values for '{}', add at least one",
+                key,
+                empty
+            ));
+        }
+
+        let combinations = matrix.combinations();
+        if combinations.len() > 1
+            && combinations
+                .iter()
+                .all(|values| substitute_str(&key, values) == key)
+        {
+            return Err(anyhow::anyhow!(
+                "The name of matrix command '{}' has to contain a matrix key, e.g. '{}-{{{{item}}}}'",
+                key,
+                key
+            ));
+        }
+
+        for values in combinations {
+            let mut copy = command.clone();
+            substitute(&mut copy, &values);
+            expand_commands(&mut copy)?;
+            insert(&mut expanded, substitute_str(&key, &values), copy)?;
+        }
+    }
+
+    *commands = expanded;
+    Ok(())
+}
+
+/// Insert an expanded command, a copy can't replace another command
+fn insert(commands: &mut Map<String, Value>, key: String, command: Value) -> Result<()> {
+    if commands.contains_key(&key) {
+        return Err(anyhow::anyhow!(
+            "Command '{}' is defined more than once after expanding the matrix",
+            key
+        ));
+    }
+
+    commands.insert(key, command);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::group::Group;
+    use serde_json::json;
+
+    #[test]
+    fn expand_matrix() {
+        struct Case {
+            name: &'static str,
+            group: Value,
+            expected: Result<Value, &'static str>,
+        }
+
+        let cases = [
+            Case {
+                name: "Items",
+                group: json!({"commands": {
+                    "deploy-{{item}}": {
+                        "command": "deploy {{item}}",
+                        "root": {"path": "services/{{item}}"},
+                        "matrix": ["api", "web"]
+                    }
+                }}),
+                expected: Ok(json!({"commands": {
+                    "deploy-api": {"command": "deploy api", "root": {"path": "services/api"}},
+                    "deploy-web": {"command": "deploy web", "root": {"path": "services/web"}}
+                }})),
+            },
+            Case {
+                name: "Named keys in a group, with nested groups",
+                group: json!({"commands": {
+                    "{{service}}": {
+                        "for_each": {"service": ["api", "web"]},
+                        "commands": {
+                            "logs-{{region}}": {
+                                "command": "logs {{service}} --region {{region}}",
+                                "matrix": {"region": ["eu"]}
+                            }
+                        }
+                    }
+                }}),
+                expected: Ok(json!({"commands": {
+                    "api": {"commands": {"logs-eu": {"command": "logs api --region eu"}}},
+                    "web": {"commands": {"logs-eu": {"command": "logs web --region eu"}}}
+                }})),
+            },
+            Case {
+                name: "Env vars",
+                group: json!({"commands": {
+                    "test-{{db}}-{{version}}": {
+                        "command": "cargo test",
+                        "envs": {"ci": {"vars": {"DATABASE": "{{db}}:{{version}}"}}},
+                        "matrix": {"db": ["postgres"], "version": ["15", "16"]}
+                    }
+                }}),
+                expected: Ok(json!({"commands": {
+                    "test-postgres-15": {
+                        "command": "cargo test",
+                        "envs": {"ci": {"vars": {"DATABASE": "postgres:15"}}}
+                    },
+                    "test-postgres-16": {
+                        "command": "cargo test",
+                        "envs": {"ci": {"vars": {"DATABASE": "postgres:16"}}}
+                    }
+                }})),
+            },
+            Case {
+                name: "Key without placeholder",
+                group: json!({"commands": {
+                    "deploy": {"command": "deploy {{item}}", "matrix": ["api", "web"]}
+                }}),
+                expected: Err(
+                    "The name of matrix command 'deploy' has to contain a matrix key, e.g. 'deploy-{{item}}'",
+                ),
+            },
+            Case {
+                name: "Matrix on the file",
+                group: json!({
+                    "matrix": ["api", "web"],
+                    "commands": {"deploy-{{item}}": "deploy {{item}}"}
+                }),
+                expected: Err(
+                    "A ds file can't have a `matrix`, add it to one of its commands or groups instead",
+                ),
+            },
+            Case {
+                name: "Empty list",
+                group: json!({"commands": {
+                    "deploy-{{item}}": {"command": "deploy {{item}}", "matrix": []}
+                }}),
+                expected: Err(
+                    "The matrix of 'deploy-{{item}}' has no values for 'item', add at least one",
+                ),
+            },
+            Case {
+                name: "Empty key",
+                group: json!({"commands": {
+                    "logs-{{service}}-{{region}}": {
+                        "command": "logs {{service}} --region {{region}}",
+                        "matrix": {"service": ["api"], "region": []}
+                    }
+                }}),
+                expected: Err(
+                    "The matrix of 'logs-{{service}}-{{region}}' has no values for 'region', add at least one",
+                ),
+            },
+            Case {
+                name: "Conflicting keys",
+                group: json!({"commands": {
+                    "deploy-api": "deploy api",
+                    "deploy-{{item}}": {"command": "deploy {{item}}", "matrix": ["api"]}
+                }}),
+                expected: Err(
+                    "Command 'deploy-api' is defined more than once after expanding the matrix",
+                ),
+            },
+        ];
+
+        for case in cases {
+            let mut group = case.group;
+            let result = expand(&mut group).map(|_| group).map_err(|e| e.to_string());
+
+            // The expanded file has to be a valid ds file
+            if let Ok(expanded) = &result {
+                let loaded = serde_json::from_value::<Group>(expanded.clone());
+                assert!(loaded.is_ok(), "{}: {:?}", case.name, loaded.err());
+            }
+            assert_eq!(result, case.expected.map_err(String::from), "{}", case.name);
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use anyhow::Result;
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+    low_level::emulate_default_handler,
+};
+use std::{
+    io::IsTerminal,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    process::{Command as ProcessCommand, ExitStatus},
+    sync::{
+        Mutex, Once,
+        atomic::{AtomicI32, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Default time to wait for the process group to exit after SIGTERM, before it is killed
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Exit code used when a command is terminated because it timed out, same as `timeout(1)`
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Settings for running a process
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessOptions {
+    /// How long to wait for the process group to exit after SIGTERM, before it is killed
+    pub grace_period: Duration,
+    /// Optional time after which the process group is terminated
+    pub timeout: Option<Duration>,
+    /// Keep the terminal, e.g. when several commands run in parallel
+    pub background: bool,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        ProcessOptions {
+            grace_period: DEFAULT_GRACE_PERIOD,
+            timeout: None,
+            background: false,
+        }
+    }
+}
+
+// The signal handling is process wide, instead of registered per run:
+// signal-hook can't unregister a handler without ignoring the signal from then on,
+// so after the first run Ctrl+C would no longer stop ds, e.g. between retry attempts.
+// It also lets several commands run at the same time, e.g. in watch mode or in parallel.
+
+/// Process groups of the running commands, signals sent to ds are forwarded to them
+static GROUPS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+/// Process groups that were stopped by ds itself, e.g. to re-run the command in watch mode
+static STOPPED: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+/// Number of commands that are being spawned, but are not in `GROUPS` yet
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// The last signal ds received, once set ds is shutting down
+static RECEIVED_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// The signal that killed the last command, e.g. SIGINT from Ctrl+C while it had the terminal
+static KILLED_BY: AtomicI32 = AtomicI32::new(0);
+
+static FORWARDER: Once = Once::new();
+
+/// Install the signal forwarder, once for the lifetime of ds
+///
+/// Signals can't be unregistered without ignoring them from then on, so the forwarder stays active.
+/// If no command is running the signal is handled like without the forwarder, which stops ds.
+fn install_forwarder() {
+    FORWARDER.call_once(|| {
+        let Ok(mut signals) = Signals::new([SIGINT, SIGTERM, SIGHUP]) else {
+            return;
+        };
+
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                RECEIVED_SIGNAL.store(signal, Ordering::SeqCst);
+                let groups = GROUPS.lock().unwrap_or_else(|e| e.into_inner());
+
+                if groups.is_empty() && PENDING.load(Ordering::SeqCst) == 0 {
+                    // Terminate by the signal itself, so the parent shell sees how ds stopped
+                    let _ = emulate_default_handler(signal);
+                    std::process::exit(128 + signal);
+                }
+
+                for pgid in groups.iter() {
+                    signal_group(*pgid, signal);
+                }
+            }
+        });
+    });
+}
+
+/// Check if ds received a signal to stop, e.g. SIGINT or SIGTERM
+pub fn interrupted() -> bool {
+    RECEIVED_SIGNAL.load(Ordering::SeqCst) != 0
+}
+
+/// Exit ds with the exit code of the command
+///
+/// If ds received a signal, or the command was killed by SIGINT or SIGTERM, ds terminates
+/// by that signal instead. The command gets the terminal, so on Ctrl+C only the command gets
+/// SIGINT. Exiting with 130 would hide it from a calling shell, e.g. a loop running `ds`
+/// would go on with the next iteration instead of stopping.
+pub fn exit_with(exit_code: i32) -> ! {
+    let signal = match RECEIVED_SIGNAL.load(Ordering::SeqCst) {
+        0 => KILLED_BY.load(Ordering::SeqCst),
+        signal => signal,
+    };
+
+    if signal != 0 {
+        let _ = emulate_default_handler(signal);
+    }
+
+    std::process::exit(exit_code);
+}
+
+/// The result of running a process
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessOutcome {
+    pub status: ExitStatus,
+    /// If the process was terminated because it timed out
+    pub timed_out: bool,
+    /// If the process was interrupted, by Ctrl+C, a signal sent to ds, or stopped by ds itself
+    pub interrupted: bool,
+    /// The signal that killed the process, if it was SIGINT or SIGTERM not sent by ds itself
+    pub killed_by: Option<i32>,
+}
+
+impl ProcessOutcome {
+    /// Get the exit code to exit ds with
+    pub fn exit_code(&self) -> i32 {
+        if self.timed_out {
+            TIMEOUT_EXIT_CODE
+        } else {
+            exit_code(self.status)
+        }
+    }
+}
+
+/// Get the exit code for a process status, using the conventional `128 + signal` if it was killed
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+/// Hands the terminal to the process group of the command, and takes it back when dropped
+///
+/// Only does something if stdin is a terminal, and we are its foreground process group.
+/// Otherwise the command would be stopped when reading from the terminal.
+struct ForegroundGuard {
+    active: bool,
+}
+
+impl ForegroundGuard {
+    fn new(pgid: i32, background: bool) -> Self {
+        let active = !background && is_foreground();
+
+        if active {
+            // SAFETY: Only changes the foreground process group of our own terminal
+            unsafe { set_foreground(pgid) };
+        }
+
+        ForegroundGuard { active }
+    }
+}
+
+impl Drop for ForegroundGuard {
+    fn drop(&mut self) {
+        if self.active {
+            // SAFETY: Only changes the foreground process group of our own terminal
+            unsafe { set_foreground(libc::getpgrp()) };
+        }
+    }
+}
+
+/// Check if stdin is a terminal, and we are in its foreground process group
+fn is_foreground() -> bool {
+    // SAFETY: Only reads the process groups
+    std::io::stdin().is_terminal()
+        && unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() }
+}
+
+/// Make a process group the foreground process group of the terminal
+///
+/// SIGTTOU is ignored while doing so, as we get it when we are not in the foreground ourselves.
+/// Only uses async-signal-safe functions, so it can be used in `pre_exec`.
+unsafe fn set_foreground(pgid: i32) {
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+/// Check if any process in the process group is still running
+pub fn group_alive(pgid: i32) -> bool {
+    // SAFETY: Signal 0 only checks if the processes exist
+    unsafe { libc::killpg(pgid, 0) == 0 }
+}
+
+/// Send a signal to the process group
+fn signal_group(pgid: i32, signal: i32) {
+    // SAFETY: Only signals the process group we created for the command
+    unsafe {
+        libc::killpg(pgid, signal);
+    }
+}
+
+/// Terminate the process group, first gracefully with SIGTERM, then forcefully with SIGKILL
+/// - Waits up to `grace_period` for the processes to exit, before killing them
+pub fn terminate_group(pgid: i32, grace_period: Duration) {
+    if !group_alive(pgid) {
+        return;
+    }
+
+    signal_group(pgid, libc::SIGTERM);
+    let start = Instant::now();
+
+    while group_alive(pgid) {
+        if start.elapsed() >= grace_period {
+            signal_group(pgid, libc::SIGKILL);
+            return;
+        }
+
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Stop all running commands, like `terminate_group` for each of their process groups
+/// - The outcome of a stopped command is marked as interrupted, so it is not retried
+pub fn stop_all(grace_period: Duration) {
+    let groups = GROUPS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+    STOPPED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .extend(&groups);
+
+    for pgid in groups {
+        terminate_group(pgid, grace_period);
+    }
+}
+
+/// Run the command in its own process group, and wait for it to finish
+/// - The command gets the terminal, so Ctrl+C goes to the command and its children
+/// - SIGINT, SIGTERM and SIGHUP sent to ds are forwarded to the process group
+/// - If it runs longer than the timeout, the process group is terminated
+/// - Processes left in the group (e.g. dev servers started by the command) are terminated afterwards
+pub fn run(cmd: &mut ProcessCommand, options: ProcessOptions) -> Result<ProcessOutcome> {
+    // Install before spawning, so a signal can't kill us before we can forward it
+    install_forwarder();
+
+    cmd.process_group(0);
+
+    if !options.background && is_foreground() {
+        // SAFETY: Only uses async-signal-safe functions in the forked child
+        unsafe {
+            cmd.pre_exec(|| {
+                set_foreground(libc::getpgrp());
+                Ok(())
+            });
+        }
+    }
+
+    PENDING.fetch_add(1, Ordering::SeqCst);
+    let child = cmd.spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            PENDING.fetch_sub(1, Ordering::SeqCst);
+            return Err(e.into());
+        }
+    };
+
+    let pgid = child.id() as i32;
+    let foreground = ForegroundGuard::new(pgid, options.background);
+
+    {
+        let mut groups = GROUPS.lock().unwrap_or_else(|e| e.into_inner());
+        groups.push(pgid);
+        PENDING.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // Forward a signal that was received while spawning
+    let received = RECEIVED_SIGNAL.load(Ordering::SeqCst);
+    if received != 0 {
+        signal_group(pgid, received);
+    }
+
+    // Terminate the process group when the timeout is reached, unless we are done before that
+    let (done, done_receiver) = mpsc::channel::<()>();
+    let timer = options.timeout.map(|timeout| {
+        thread::spawn(move || {
+            let timed_out =
+                done_receiver.recv_timeout(timeout) == Err(mpsc::RecvTimeoutError::Timeout);
+
+            if timed_out {
+                terminate_group(pgid, options.grace_period);
+            }
+
+            timed_out
+        })
+    });
+
+    let status = child.wait()?;
+
+    // Take back the terminal, and stop the timer
+    drop(foreground);
+    drop(done);
+    let timed_out = timer.is_some_and(|timer| timer.join().unwrap_or(false));
+
+    terminate_group(pgid, options.grace_period);
+    GROUPS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|group| *group != pgid);
+
+    let stopped = {
+        let mut stopped = STOPPED.lock().unwrap_or_else(|e| e.into_inner());
+        let was_stopped = stopped.contains(&pgid);
+        stopped.retain(|group| *group != pgid);
+        was_stopped
+    };
+
+    // A timeout or a re-run in watch mode terminates the command as well, that is not passed on
+    let killed_by = status
+        .signal()
+        .filter(|signal| [libc::SIGINT, libc::SIGTERM].contains(signal))
+        .filter(|_| !timed_out && !stopped);
+
+    if let Some(signal) = killed_by {
+        KILLED_BY.store(signal, Ordering::SeqCst);
+    }
+
+    Ok(ProcessOutcome {
+        status,
+        timed_out,
+        // Commands that handle Ctrl+C themselves often exit with 130 (or 143 on SIGTERM)
+        interrupted: interrupted()
+            || stopped
+            || killed_by.is_some()
+            || matches!(status.code(), Some(130 | 143)),
+        killed_by,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), 3);
+        assert_eq!(exit_code(ExitStatus::from_raw(libc::SIGINT)), 130);
+        assert_eq!(exit_code(ExitStatus::from_raw(libc::SIGKILL)), 137);
+    }
+
+    #[test]
+    fn cleans_up_process_group() {
+        let mut cmd = ProcessCommand::new("sh");
+        cmd.arg("-c").arg("sleep 30 & exit 3");
+
+        let options = ProcessOptions {
+            grace_period: Duration::from_millis(200),
+            timeout: None,
+            background: false,
+        };
+
+        let start = Instant::now();
+        let outcome = run(&mut cmd, options).unwrap();
+
+        assert_eq!(outcome.exit_code(), 3);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn terminates_on_timeout() {
+        let mut cmd = ProcessCommand::new("sh");
+        cmd.arg("-c").arg("sleep 30");
+
+        let options = ProcessOptions {
+            grace_period: Duration::from_millis(200),
+            timeout: Some(Duration::from_millis(100)),
+            background: false,
+        };
+
+        let start = Instant::now();
+        let outcome = run(&mut cmd, options).unwrap();
+
+        assert!(outcome.timed_out);
+        assert_eq!(outcome.exit_code(), TIMEOUT_EXIT_CODE);
+        assert_eq!(outcome.killed_by, None, "Terminated by ds itself");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn killed_by_signal() {
+        let mut cmd = ProcessCommand::new("sh");
+        cmd.arg("-c").arg("kill -TERM $$");
+
+        let outcome = run(&mut cmd, ProcessOptions::default()).unwrap();
+
+        assert_eq!(outcome.killed_by, Some(libc::SIGTERM));
+        assert_eq!(outcome.exit_code(), 128 + libc::SIGTERM);
+        assert!(outcome.interrupted);
+    }
+}
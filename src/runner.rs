@@ -1,87 +1,459 @@
 use crate::{
-    command::{Command, CommandConfig},
-    env::{Env, RunnerEnv},
+    command::{Command, CommandBody, CommandLine, Hooks, RetryConfig, Shell, script_summary},
+    env::{Env, EnvIsolation, RequiredVar, RunnerEnv, check_required_vars},
     group::Group,
+    lock::LockConfig,
+    process::{self, ProcessOptions, ProcessOutcome},
+    service::Services,
+    wait::ReadyCheck,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
+use crossterm::style::Stylize;
 use shell_escape::escape;
 use std::{
     borrow::Cow,
-    io::IsTerminal,
-    path::Path,
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{IsTerminal, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
     process::{Command as ProcessCommand, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
 };
 
-/// Create a command to run in the shell
+/// Settings to create the process command with, resolved from the command and its parents
+#[derive(Debug, Default)]
+pub struct CommandOptions<'a> {
+    /// The directory to run the command from
+    pub work_dir: Option<PathBuf>,
+    /// The shell to run command strings with, defaults to `sh`
+    pub shell: Option<&'a Shell>,
+    /// Variables that must be set before the command is run
+    pub required_vars: Vec<&'a RequiredVar>,
+    /// Which variables of the current shell environment are passed on
+    pub isolation: EnvIsolation,
+}
+
+/// A lifecycle hook, ready to run with the same env and root as the command
+#[derive(Debug)]
+pub struct Hook {
+    pub command_str: String,
+    pub command: ProcessCommand,
+}
+
+/// The lifecycle hooks to run around the command
+#[derive(Debug, Default)]
+pub struct RunHooks {
+    pub before: Vec<Hook>,
+    pub after: Vec<Hook>,
+    pub on_failure: Vec<Hook>,
+}
+
+impl RunHooks {
+    /// Create the hook commands, with the same env and options as the command
+    fn new(
+        hooks: Hooks,
+        env: Option<(&str, &Env)>,
+        options: &CommandOptions,
+        file_path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let create = |hooks: Vec<&str>| -> Result<Vec<Hook>> {
+            hooks
+                .into_iter()
+                .map(|hook| {
+                    let command = CommandLine::Shell(hook.to_string());
+                    let (command, command_str) =
+                        create_command(&command, &[], env, options, &file_path)?;
+                    Ok(Hook {
+                        command_str,
+                        command,
+                    })
+                })
+                .collect()
+        };
+
+        Ok(RunHooks {
+            before: create(hooks.before)?,
+            after: create(hooks.after)?,
+            on_failure: create(hooks.on_failure)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.before.is_empty() && self.after.is_empty() && self.on_failure.is_empty()
+    }
+}
+
+/// Run the hooks in order, labelled with their kind, e.g. `[before] docker compose up -d`
+/// - Stops at the first hook that fails, and returns its exit code
+/// - A hook that can't be started is logged and fails with exit code 1, so the exit code of
+///   the command is kept when an `after` hook fails
+pub fn run_hooks(label: &str, hooks: &mut [Hook], options: ProcessOptions) -> i32 {
+    for hook in hooks {
+        println!("{}", format!("[{}] {}", label, hook.command_str).dim());
+
+        let exit_code = match process::run(&mut hook.command, options) {
+            Ok(outcome) => outcome.exit_code(),
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    format!("[{}] {} failed to start: {:#}", label, hook.command_str, e).red()
+                );
+                return 1;
+            }
+        };
+        if exit_code != 0 {
+            eprintln!(
+                "{}",
+                format!(
+                    "[{}] {} failed with exit code {}",
+                    label, hook.command_str, exit_code
+                )
+                .red()
+            );
+            return exit_code;
+        }
+    }
+
+    0
+}
+
+/// A script to write to a temporary file when the command runs, the file is removed when dropped
+#[derive(Debug)]
+pub struct ScriptFile {
+    pub path: PathBuf,
+    script: String,
+}
+
+impl ScriptFile {
+    /// Pick a new file in the directory for the script, it is only written by `write`
+    fn new(script: &str, dir: &Path) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = dir.join(format!(
+            "ds-script-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        ScriptFile {
+            path,
+            script: script.to_string(),
+        }
+    }
+
+    /// Write the script to its file, only accessible by the current user
+    pub fn write(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o700)
+            .open(&self.path)?;
+        file.write_all(self.script.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Keep the file after the script is done, e.g. for a service that outlives ds
+    pub fn keep(mut self) -> PathBuf {
+        std::mem::take(&mut self.path)
+    }
+
+    /// Get the argv to run the script with
+    /// - With the interpreter if set, otherwise directly if it has a shebang, or with the shell
+    /// - The shell runs the file instead of a command string, so a trailing `-c` of its argv is dropped
+    fn argv(&self, interpreter: Option<&[String]>, shell: &Shell) -> Vec<String> {
+        let path = self.path.to_string_lossy().to_string();
+
+        match interpreter {
+            Some(interpreter) => interpreter.iter().cloned().chain([path]).collect(),
+            None if self.script.starts_with("#!") => vec![path],
+            None => {
+                let mut argv = shell.argv();
+                if argv.len() > 1 && argv.last() == Some(&"-c") {
+                    argv.pop();
+                }
+                argv.into_iter().map(String::from).chain([path]).collect()
+            }
+        }
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Settings for running the process, resolved from the command and its parents
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    /// How long to wait for the process group to exit after SIGTERM, before it is killed
+    pub grace_period: Option<Duration>,
+    /// Time after which the process group is terminated
+    pub timeout: Option<Duration>,
+    /// How to retry the command when it fails
+    pub retry: Option<RetryConfig>,
+    /// Globs of the files that re-run the command in watch mode
+    pub watch: Option<Vec<String>>,
+    /// Save the output to a log file, falls back to the global config if not set
+    pub log: Option<bool>,
+    /// The env the command is run with, if any
+    pub env: Option<String>,
+    /// The extra args passed on to the command
+    pub args: Vec<String>,
+    /// The lifecycle hooks to run around the command
+    pub hooks: RunHooks,
+    /// The answer to type to confirm running the command, if it has to be confirmed
+    pub confirm: Option<String>,
+    /// The temporary file of a script, written when the command runs and removed when the
+    /// options are dropped
+    pub script: Option<ScriptFile>,
+    /// Whether the command can be run in the background with `ds start`
+    pub service: bool,
+    /// Probes that have to succeed before the command runs, check commands run like the command
+    pub wait_for: Vec<ReadyCheck>,
+    /// Globs of the files the command reads and creates, to skip it when it is up to date
+    pub inputs_outputs: Option<(Vec<String>, Vec<String>)>,
+    /// The lock to take before the command and its hooks run, if any
+    pub lock: Option<LockConfig>,
+}
+
+impl RunOptions {
+    /// Write the script file of the command, if it runs a script, right before running it
+    pub fn write_script(&self) -> Result<()> {
+        match &self.script {
+            Some(script) => script.write(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run the process, retrying failed attempts as configured
+/// - Every attempt runs the same process command, so with the same env and root
+/// - Attempts that are interrupted (e.g. Ctrl+C) are not retried
+pub fn run_with_retry(
+    cmd: &mut ProcessCommand,
+    options: ProcessOptions,
+    retry: Option<&RetryConfig>,
+) -> Result<ProcessOutcome> {
+    let attempts = retry.map_or(1, |retry| retry.attempts.max(1));
+    let mut delay = retry
+        .and_then(|retry| retry.delay)
+        .map_or(Duration::ZERO, |d| d.0);
+    let mut attempt = 1;
+
+    loop {
+        let outcome = process::run(cmd, options)?;
+        let exit_code = outcome.exit_code();
+
+        let retryable = !outcome.interrupted
+            && attempt < attempts
+            && retry.is_some_and(|retry| retry.is_retryable(exit_code));
+
+        if !retryable {
+            return Ok(outcome);
+        }
+
+        eprintln!(
+            "{}",
+            format!(
+                "Attempt {}/{} failed with exit code {}, retrying in {}",
+                attempt,
+                attempts,
+                exit_code,
+                humantime::format_duration(delay)
+            )
+            .yellow()
+        );
+
+        thread::sleep(delay);
+        // The backoff is at least 1, so the delay only overflows after many attempts
+        let backoff = retry.and_then(|retry| retry.backoff).unwrap_or(1.0);
+        delay = Duration::try_from_secs_f64(delay.as_secs_f64() * backoff).unwrap_or(Duration::MAX);
+        attempt += 1;
+    }
+}
+
+/// Escape the args and join them with spaces, to show or run them in a shell
+fn join_args<'a>(args: impl IntoIterator<Item = &'a str>) -> String {
+    args.into_iter()
+        .map(|arg| escape(Cow::Borrowed(arg)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Vars that ask tools for colored output, set when ds itself runs in a terminal
+pub const COLOR_VARS: &[&str] = &["CLICOLOR", "CLICOLOR_FORCE", "FORCE_COLOR"];
+
+/// Create a command to run in the shell, or directly for exec commands
 fn create_command(
-    command: &str,
-    work_dir: Option<impl AsRef<Path>>,
+    command: &CommandLine,
     args: &[&str],
-    env: Option<&Env>,
+    env: Option<(&str, &Env)>,
+    options: &CommandOptions,
     file_path: impl AsRef<Path>,
 ) -> Result<(ProcessCommand, String)> {
-    let mut cmd = ProcessCommand::new("sh");
-    let mut command_str = command.to_string();
+    let mut isolation = options.isolation.clone();
+    let mut required = options.required_vars.clone();
+    let mut prefix = None;
+    let mut vars = BTreeMap::new();
+    let mut env_paths = Vec::new();
 
     // Handle environment
-    if let Some(env) = env {
-        let RunnerEnv { command, vars } = env.get_env_vars(file_path)?;
+    if let Some((key, env)) = env {
+        let RunnerEnv {
+            command,
+            vars: env_vars,
+        } = env
+            .get_env_vars(file_path)
+            .with_context(|| format!("Failed to load env '{}'", key))?;
+
+        prefix = command;
+        required.extend(env.required_vars());
+        env.merge_isolation(&mut isolation);
+        env_paths = env.paths();
+        vars = env_vars.unwrap_or_default();
+    }
 
-        // Prepend the command if specified
-        if let Some(cmd) = command {
-            command_str = format!("{} {}", cmd, command_str);
+    // Validate the required variables, against the environment the command will actually get
+    let mut visible_vars = isolation.inherited_vars();
+    visible_vars.extend(vars.clone());
+    check_required_vars(
+        &required,
+        &visible_vars,
+        env.map(|(key, _)| key),
+        &env_paths,
+    )?;
+
+    let (mut cmd, command_str) = match command {
+        CommandLine::Shell(command) => {
+            let mut command_str = command.to_string();
+
+            // Prepend the command if specified
+            if let Some(prefix) = prefix {
+                command_str = format!("{} {}", prefix, command_str);
+            }
+
+            let shell = options.shell.cloned().unwrap_or_default();
+            let shell_argv = shell.argv();
+            let Some((program, shell_args)) = shell_argv.split_first() else {
+                return Err(anyhow::anyhow!("Shell can not be empty"));
+            };
+
+            let mut cmd = ProcessCommand::new(program);
+            cmd.args(shell_args);
+
+            if shell.is_posix() {
+                for arg in args {
+                    command_str.push(' ');
+                    command_str.push_str(&escape(Cow::Borrowed(arg)));
+                }
+                cmd.arg(&command_str);
+            } else {
+                // POSIX escaping means nothing to other shells, they get the args after the command
+                cmd.arg(&command_str);
+                cmd.args(args);
+                if !args.is_empty() {
+                    command_str = format!("{} {}", command_str, join_args(args.iter().copied()));
+                }
+            }
+
+            (cmd, command_str)
         }
+        CommandLine::Exec(argv) => {
+            // Extra args are passed on as separate arguments, without any escaping
+            let argv: Vec<&str> = argv
+                .iter()
+                .map(|arg| arg.as_str())
+                .chain(args.iter().copied())
+                .collect();
+
+            let Some((program, rest)) = argv.split_first() else {
+                return Err(anyhow::anyhow!("Exec command can not be empty"));
+            };
+
+            if let Some(prefix) = prefix {
+                // Let the shell handle the prefix, while passing the args through untouched
+                // Passing them on with "$@" needs a POSIX shell, others fall back to `sh`
+                let shell = options
+                    .shell
+                    .filter(|shell| shell.is_posix())
+                    .cloned()
+                    .unwrap_or_default();
+                let shell_argv = shell.argv();
+                let Some((shell_program, shell_args)) = shell_argv.split_first() else {
+                    return Err(anyhow::anyhow!("Shell can not be empty"));
+                };
 
-        // Set the custom environment variables
-        if let Some(vars) = vars {
-            cmd.envs(vars);
+                let mut cmd = ProcessCommand::new(shell_program);
+                cmd.args(shell_args);
+                cmd.arg(format!("{} \"$@\"", prefix));
+                cmd.arg(shell_program);
+                cmd.args(&argv);
+                (cmd, format!("{} {}", prefix, join_args(argv)))
+            } else {
+                let mut cmd = ProcessCommand::new(program);
+                cmd.args(rest);
+                (cmd, join_args(argv))
+            }
         }
-    }
+    };
 
-    for arg in args {
-        command_str.push(' ');
-        command_str.push_str(&escape(Cow::Borrowed(arg)));
-    }
+    // Clean/unset the inherited environment, before setting the custom environment variables
+    isolation.apply(&mut cmd);
+    cmd.envs(vars);
 
-    cmd.arg("-c");
-    cmd.arg(&command_str);
     cmd.stdin(Stdio::inherit());
     cmd.stdout(Stdio::inherit());
     cmd.stderr(Stdio::inherit());
 
-    if let Some(dir) = work_dir {
+    if let Some(dir) = &options.work_dir {
         cmd.current_dir(dir);
     }
 
     if std::io::stdout().is_terminal() {
-        cmd.env("CLICOLOR", "1");
-        cmd.env("CLICOLOR_FORCE", "1");
-        cmd.env("FORCE_COLOR", "1");
+        for var in COLOR_VARS {
+            cmd.env(var, "1");
+        }
     }
 
     Ok((cmd, command_str))
 }
 
 /// Enum representing the type of command runner
-/// - `Command` is a command to run
+/// - `Command` is a command to run, with the settings to run it with
 /// - `Help` is a help group that provides information about commands
 #[derive(Debug)]
 pub enum Runner {
-    Command(String, Box<ProcessCommand>),
+    Command(String, Box<ProcessCommand>, Box<RunOptions>),
     Help,
 }
 
 impl Runner {
     /// Create a new command runner
     pub fn new_command(
-        command: &str,
-        path: Option<impl AsRef<Path>>,
+        command: &CommandLine,
         args: &[&str],
-        env: Option<&Env>,
+        env: Option<(&str, &Env)>,
+        options: &CommandOptions,
+        run_options: RunOptions,
         file_path: impl AsRef<Path>,
     ) -> Result<Self> {
-        let (cmd, cmd_str) = create_command(command, path, args, env, file_path)?;
-        Ok(Runner::Command(cmd_str, Box::new(cmd)))
+        let (cmd, cmd_str) = create_command(command, args, env, options, file_path)?;
+        Ok(Runner::Command(
+            cmd_str,
+            Box::new(cmd),
+            Box::new(run_options),
+        ))
     }
 
     /// Get the command runner for a given command definition
@@ -92,21 +464,203 @@ impl Runner {
         command: &Command,
         parents: &[&Group],
         extra_args: &[&str],
-        env: Option<&Env>,
+        env: Option<(&str, &Env)>,
         file_path: impl AsRef<Path>,
     ) -> Result<Self> {
-        let path = command.resolve_root_path(parents, &file_path)?;
+        let options = CommandOptions {
+            work_dir: command.resolve_root_path(parents, &file_path)?,
+            shell: command.resolve_shell(parents),
+            required_vars: command.resolve_required_vars(parents),
+            isolation: command.env_isolation(),
+        };
+
+        let mut run_options = RunOptions {
+            grace_period: command.resolve_grace_period(parents),
+            timeout: command.resolve_timeout(parents),
+            retry: command.resolve_retry(parents).cloned(),
+            watch: command.resolve_watch(parents).map(<[String]>::to_vec),
+            log: command.resolve_log(parents),
+            env: env.map(|(key, _)| key.to_string()),
+            args: extra_args.iter().map(|arg| arg.to_string()).collect(),
+            // Confirming an env is done by typing its name, to avoid running in the wrong one
+            confirm: match env {
+                Some((key, env)) if env.confirm() => Some(key.to_string()),
+                _ if command.resolve_confirm(parents) => Some("yes".to_string()),
+                _ => None,
+            },
+            hooks: match command {
+                Command::Group(_) => RunHooks::default(),
+                _ => RunHooks::new(command.resolve_hooks(parents), env, &options, &file_path)?,
+            },
+            script: None,
+            service: command.resolve_service(parents),
+            wait_for: command
+                .wait_for()
+                .iter()
+                .map(|wait| {
+                    ReadyCheck::new(wait.clone(), |check| {
+                        let check = CommandLine::Shell(check.to_string());
+                        Ok(create_command(&check, &[], env, &options, &file_path)?.0)
+                    })
+                })
+                .collect::<Result<_>>()?,
+            inputs_outputs: command
+                .inputs_outputs()
+                .map(|(inputs, outputs)| (inputs.to_vec(), outputs.to_vec())),
+            lock: command.resolve_lock(parents),
+        };
 
         let runner = match command {
-            Command::Inline(cmd) => {
-                Runner::new_command(cmd, path.as_ref(), extra_args, env, file_path)?
-            }
-            Command::Config(CommandConfig { command: cmd, .. }) => {
-                Runner::new_command(cmd, path.as_ref(), extra_args, env, file_path)?
-            }
+            Command::Inline(cmd) => Runner::new_command(
+                &CommandLine::Shell(cmd.clone()),
+                extra_args,
+                env,
+                &options,
+                run_options,
+                file_path,
+            )?,
+            Command::Config(config) => match config.body()? {
+                CommandBody::Command(cmd) => {
+                    Runner::new_command(cmd, extra_args, env, &options, run_options, file_path)?
+                }
+                CommandBody::Script(script) => {
+                    // Run the script file directly, so extra args are passed on as its argv
+                    // A service still needs its script after ds exits, so it is kept with the service
+                    let dir = match run_options.service {
+                        true => Services::new(file_path.as_ref())?.dir,
+                        false => std::env::temp_dir(),
+                    };
+                    let file = ScriptFile::new(script, &dir);
+                    let shell = options.shell.cloned().unwrap_or_default();
+                    let argv = file.argv(config.interpreter.as_deref(), &shell);
+                    let (cmd, _) = create_command(
+                        &CommandLine::Exec(argv),
+                        extra_args,
+                        env,
+                        &options,
+                        file_path,
+                    )?;
+
+                    let mut cmd_str = script_summary(script);
+                    if !extra_args.is_empty() {
+                        cmd_str = format!("{} {}", cmd_str, join_args(extra_args.iter().copied()));
+                    }
+
+                    run_options.script = Some(file);
+                    Runner::Command(cmd_str, Box::new(cmd), Box::new(run_options))
+                }
+            },
             Command::Group(_group) => Runner::Help,
         };
 
         Ok(runner)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::TIMEOUT_EXIT_CODE;
+
+    #[test]
+    fn retries_failed_attempts() {
+        struct Case {
+            name: &'static str,
+            exit_code: i32,
+            exit_codes: Option<Vec<i32>>,
+            expected_attempts: usize,
+        }
+
+        let cases = [
+            Case {
+                name: "Retries any non-zero exit code",
+                exit_code: 3,
+                exit_codes: None,
+                expected_attempts: 3,
+            },
+            Case {
+                name: "Retries matching exit codes",
+                exit_code: 3,
+                exit_codes: Some(vec![1, 3]),
+                expected_attempts: 3,
+            },
+            Case {
+                name: "Does not retry other exit codes",
+                exit_code: 3,
+                exit_codes: Some(vec![1]),
+                expected_attempts: 1,
+            },
+            Case {
+                name: "Does not retry timeouts by default",
+                exit_code: TIMEOUT_EXIT_CODE,
+                exit_codes: None,
+                expected_attempts: 1,
+            },
+            Case {
+                name: "Retries timeouts if listed",
+                exit_code: TIMEOUT_EXIT_CODE,
+                exit_codes: Some(vec![TIMEOUT_EXIT_CODE]),
+                expected_attempts: 3,
+            },
+            Case {
+                name: "Does not retry a command that exits after Ctrl+C",
+                exit_code: 130,
+                exit_codes: None,
+                expected_attempts: 1,
+            },
+        ];
+
+        for (i, case) in cases.into_iter().enumerate() {
+            let log = std::env::temp_dir().join(format!("ds-retry-{}-{}", std::process::id(), i));
+            let _ = std::fs::remove_file(&log);
+
+            let mut cmd = ProcessCommand::new("sh");
+            cmd.arg("-c")
+                .arg(format!("echo attempt >> \"$LOG\"; exit {}", case.exit_code));
+            cmd.env("LOG", &log);
+
+            let retry = RetryConfig {
+                attempts: 3,
+                delay: None,
+                backoff: None,
+                exit_codes: case.exit_codes,
+            };
+
+            let outcome =
+                run_with_retry(&mut cmd, ProcessOptions::default(), Some(&retry)).unwrap();
+            let attempts = std::fs::read_to_string(&log).unwrap().lines().count();
+            let _ = std::fs::remove_file(&log);
+
+            assert_eq!(outcome.exit_code(), case.exit_code, "{}", case.name);
+            assert_eq!(attempts, case.expected_attempts, "{}", case.name);
+        }
+
+        for backoff in ["-1", "0.5"] {
+            let json = format!(r#"{{"attempts": 3, "backoff": {}}}"#, backoff);
+            let err = serde_json::from_str::<RetryConfig>(&json).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("backoff has to be a number of at least 1"),
+                "{}",
+                backoff
+            );
+        }
+    }
+
+    #[test]
+    fn hooks_that_fail_to_start() {
+        let hook = |command_str: &str, program: &str| Hook {
+            command_str: command_str.to_string(),
+            command: ProcessCommand::new(program),
+        };
+
+        let mut hooks = vec![
+            hook("true", "true"),
+            hook("missing", "/nonexistent/ds-hook"),
+        ];
+        assert_eq!(run_hooks("after", &mut hooks, ProcessOptions::default()), 1);
+
+        let mut hooks = vec![hook("true", "true")];
+        assert_eq!(run_hooks("after", &mut hooks, ProcessOptions::default()), 0);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::{
+    config::get_state_dir,
+    dir::{keys_file_name, keys_from_file_name, path_id},
+    process::{group_alive, terminate_group},
+};
+use anyhow::Result;
+use crossterm::style::Stylize;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command as ProcessCommand, Stdio},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// The services of a ds file, tracked by pid files in the state directory
+pub struct Services {
+    pub dir: PathBuf,
+}
+
+impl Services {
+    /// Get the services directory of a ds file, e.g. `~/.local/state/do-something/services/<id>`
+    pub fn new(file_path: &Path) -> Result<Self> {
+        let state_dir = get_state_dir().ok_or(anyhow::anyhow!("Could not find state directory"))?;
+
+        Ok(Services {
+            dir: state_dir.join("services").join(path_id(file_path)),
+        })
+    }
+
+    /// Get a service by the keys of its command
+    pub fn get(&self, keys: &[String]) -> Service {
+        Service {
+            keys: keys.to_vec(),
+            pid_path: self.dir.join(format!("{}.pid", keys_file_name(keys))),
+        }
+    }
+
+    /// List the services with a pid file, sorted by their keys
+    pub fn list(&self) -> Result<Vec<Service>> {
+        if !self.dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut services = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "pid")
+                && let Some(name) = path.file_stem()
+            {
+                let keys = keys_from_file_name(&name.to_string_lossy());
+                services.push(Service {
+                    keys,
+                    pid_path: path,
+                });
+            }
+        }
+
+        services.sort_by(|a, b| a.keys.cmp(&b.keys));
+        Ok(services)
+    }
+}
+
+/// Get the start time of a process, in clock ticks since boot, from `/proc/<pid>/stat`
+/// - None if it isn't running
+#[cfg(target_os = "linux")]
+fn process_start_time(pid: i32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // The name in parentheses can contain spaces, the start time is the 20th field after it
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Get the start time of a process, in microseconds since the epoch, macOS has no `/proc`
+/// - None if it isn't running
+#[cfg(target_os = "macos")]
+fn process_start_time(pid: i32) -> Option<u64> {
+    let size = std::mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    // SAFETY: The struct only holds numbers and arrays, so all zeroes is a valid value
+    let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+
+    // SAFETY: Only writes the info of the process into the struct, up to its size
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            (&mut info as *mut libc::proc_bsdinfo).cast(),
+            size,
+        )
+    };
+
+    (written == size).then(|| info.pbi_start_tvsec * 1_000_000 + info.pbi_start_tvusec)
+}
+
+/// Other systems don't expose the start time in a common way
+/// - Always None, so a reused pid is only detected by its session, see `ServiceState::is_running`
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn process_start_time(_pid: i32) -> Option<u64> {
+    None
+}
+
+/// What is stored in the pid file of a service
+#[derive(Debug, Serialize, Deserialize)]
+struct ServiceState {
+    /// The pid of the service, which is also its session and process group id
+    pid: i32,
+    /// The start time of the process, to detect a reused pid
+    start_time: Option<u64>,
+    /// The script file the service runs, if any, removed when it is stopped
+    script: Option<PathBuf>,
+}
+
+impl ServiceState {
+    /// Check that the process group still belongs to the service, and not to a reused pid
+    /// - While the service process runs, it has to lead its own session, with the same start time
+    /// - When only other processes of its group are left, the pid can't be reused
+    fn is_running(&self) -> bool {
+        if !group_alive(self.pid) {
+            return false;
+        }
+
+        // SAFETY: Only gets the session id of the process
+        let sid = unsafe { libc::getsid(self.pid) };
+        if sid == -1 {
+            return io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH);
+        }
+
+        sid == self.pid && process_start_time(self.pid) == self.start_time
+    }
+
+    fn remove_script(&self) -> Result<()> {
+        if let Some(script) = &self.script
+            && script.exists()
+        {
+            fs::remove_file(script)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A command running in the background, in its own session
+#[derive(Debug)]
+pub struct Service {
+    pub keys: Vec<String>,
+    pid_path: PathBuf,
+}
+
+impl Service {
+    fn state(&self) -> Option<ServiceState> {
+        serde_json::from_str(&fs::read_to_string(&self.pid_path).ok()?).ok()
+    }
+
+    /// Get the pid of the service, if it was started and is still running
+    /// - A pid file of a service that exited is left behind, until it is started or stopped again
+    pub fn running_pid(&self) -> Option<i32> {
+        self.state()
+            .filter(ServiceState::is_running)
+            .map(|state| state.pid)
+    }
+
+    /// When the service was started, from the pid file
+    pub fn started(&self) -> Option<SystemTime> {
+        self.pid_path.metadata().ok()?.modified().ok()
+    }
+
+    /// Start the command detached from the terminal, with its output going to the log file
+    /// - The command gets its own session, so it keeps running when the terminal is closed
+    /// - The script file the command runs, if any, is removed when the service is stopped
+    pub fn start(
+        &self,
+        cmd: &mut ProcessCommand,
+        log: File,
+        script: Option<PathBuf>,
+    ) -> Result<i32> {
+        match self.state() {
+            Some(state) if state.is_running() => {
+                return Err(anyhow::anyhow!(
+                    "{} is already running with pid {}",
+                    self.keys.join(" "),
+                    state.pid
+                ));
+            }
+            Some(state) => state.remove_script()?,
+            None => {}
+        }
+
+        cmd.stdin(Stdio::null());
+        cmd.stdout(log.try_clone()?);
+        cmd.stderr(log);
+
+        // SAFETY: setsid is async-signal-safe, and only affects the child
+        unsafe {
+            cmd.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+
+        let mut child = cmd.spawn()?;
+        let pid = child.id() as i32;
+
+        // Reap the child if it exits while we are still running, so it isn't seen as alive
+        thread::spawn(move || child.wait());
+
+        if let Some(dir) = self.pid_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let state = ServiceState {
+            pid,
+            start_time: process_start_time(pid),
+            script,
+        };
+        fs::write(&self.pid_path, serde_json::to_string(&state)?)?;
+
+        Ok(pid)
+    }
+
+    /// Stop the service like a command on Ctrl+C, and remove its pid file and script
+    /// - Returns the pid of the stopped service, or None if it wasn't running
+    pub fn stop(&self, grace_period: Duration) -> Result<Option<i32>> {
+        let state = self.state();
+        let pid = state
+            .as_ref()
+            .filter(|state| state.is_running())
+            .map(|state| state.pid);
+        if let Some(pid) = pid {
+            terminate_group(pid, grace_period);
+        }
+
+        if let Some(state) = &state {
+            state.remove_script()?;
+        }
+        if self.pid_path.exists() {
+            fs::remove_file(&self.pid_path)?;
+        }
+
+        Ok(pid)
+    }
+}
+
+/// Print the status of the services, with how long they are running
+pub fn print_status(services: &[Service]) {
+    let width = services
+        .iter()
+        .map(|service| service.keys.join(" ").len())
+        .max()
+        .unwrap_or(0);
+
+    for service in services {
+        let name = service.keys.join(" ");
+
+        match service.running_pid() {
+            Some(pid) => {
+                let uptime = service
+                    .started()
+                    .and_then(|started| started.elapsed().ok())
+                    .map(|elapsed| Duration::from_secs(elapsed.as_secs()))
+                    .unwrap_or_default();
+
+                println!(
+                    "{} {:width$}  {}  {}",
+                    "●".green(),
+                    name,
+                    format!("pid {}", pid).dim(),
+                    format!("up {}", humantime::format_duration(uptime)).dim(),
+                    width = width,
+                );
+            }
+            None => println!("{} {:width$}  {}", "●".red(), name, "exited".dim()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_and_stop() {
+        let dir = std::env::temp_dir().join(format!("ds-services-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let services = Services { dir: dir.clone() };
+        let keys = vec!["api".to_string(), "dev".to_string()];
+        let service = services.get(&keys);
+        assert_eq!(service.running_pid(), None);
+
+        fs::create_dir_all(&dir).unwrap();
+        let log_path = dir.join("output.log");
+        let mut cmd = ProcessCommand::new("sh");
+        cmd.arg("-c").arg("echo started; sleep 30");
+
+        let script = dir.join("script.sh");
+        fs::write(&script, "sleep 30").unwrap();
+
+        let pid = service
+            .start(
+                &mut cmd,
+                File::create(&log_path).unwrap(),
+                Some(script.clone()),
+            )
+            .unwrap();
+        assert_eq!(service.running_pid(), Some(pid));
+
+        let mut cmd = ProcessCommand::new("true");
+        let log = File::create(&log_path).unwrap();
+        assert!(
+            service.start(&mut cmd, log, None).is_err(),
+            "Already running"
+        );
+
+        let listed = services.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].keys, keys);
+
+        assert_eq!(service.stop(Duration::from_secs(1)).unwrap(), Some(pid));
+        assert_eq!(service.running_pid(), None);
+        assert!(!script.exists(), "The script is removed");
+        assert!(services.list().unwrap().is_empty());
+        assert_eq!(service.stop(Duration::from_secs(1)).unwrap(), None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reused_pid_is_not_stopped() {
+        let dir = std::env::temp_dir().join(format!("ds-services-reused-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let services = Services { dir: dir.clone() };
+        let service = services.get(&["db".to_string()]);
+        let mut cmd = ProcessCommand::new("sleep");
+        cmd.arg("30");
+        let log = File::create(dir.join("output.log")).unwrap();
+        let pid = service.start(&mut cmd, log, None).unwrap();
+
+        // Pretend the pid file belongs to an earlier process with the same pid
+        let mut state = service.state().unwrap();
+        let start_time = state.start_time;
+        state.start_time = Some(start_time.unwrap_or(0) + 1);
+        fs::write(&service.pid_path, serde_json::to_string(&state).unwrap()).unwrap();
+
+        if start_time.is_some() {
+            assert_eq!(service.running_pid(), None);
+            assert_eq!(service.stop(Duration::from_secs(1)).unwrap(), None);
+            assert!(group_alive(pid), "The process is left alone");
+        }
+
+        terminate_group(pid, Duration::from_secs(1));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,189 @@
+use crossterm::style::Stylize;
+use std::{
+    io::{IsTerminal, Write},
+    time::Duration,
+};
+
+/// Format the wall time of a run, with more precision for short runs
+fn format_elapsed(elapsed: Duration) -> String {
+    let rounded = if elapsed >= Duration::from_secs(60) {
+        Duration::from_secs(elapsed.as_secs())
+    } else {
+        Duration::from_millis(elapsed.as_millis() as u64)
+    };
+
+    humantime::format_duration(rounded).to_string()
+}
+
+/// The summary of a finished run, e.g. `✔ cargo build finished in 1m 3s`
+pub fn summary_line(cmd_str: &str, exit_code: i32, elapsed: Duration) -> String {
+    match exit_code {
+        0 => format!("✔ {} finished in {}", cmd_str, format_elapsed(elapsed)),
+        code => format!(
+            "✘ {} failed with exit code {} after {}",
+            cmd_str,
+            code,
+            format_elapsed(elapsed)
+        ),
+    }
+}
+
+/// Print the summary of a finished run to stderr
+pub fn print_summary(cmd_str: &str, exit_code: i32, elapsed: Duration) {
+    let line = summary_line(cmd_str, exit_code, elapsed);
+
+    match exit_code {
+        0 => eprintln!("{}", line.green()),
+        _ => eprintln!("{}", line.red()),
+    }
+}
+
+/// A row in the results of `ds run`
+pub struct RunRow {
+    pub name: String,
+    /// The exit code and wall time, or None if the command was skipped
+    pub finished: Option<(i32, Duration)>,
+}
+
+/// Format a row of the results, with the name padded to `width`
+fn result_line(row: &RunRow, width: usize) -> String {
+    match row.finished {
+        Some((0, elapsed)) => format!("✔ {:width$}  passed  {}", row.name, format_elapsed(elapsed)),
+        Some((code, elapsed)) => format!(
+            "✘ {:width$}  failed  {} (exit code {})",
+            row.name,
+            format_elapsed(elapsed),
+            code
+        ),
+        None => format!("- {:width$}  skipped", row.name),
+    }
+}
+
+/// Print the results of running several commands as a table to stderr
+pub fn print_results(rows: &[RunRow]) {
+    let width = rows.iter().map(|row| row.name.len()).max().unwrap_or(0);
+
+    eprintln!();
+    for row in rows {
+        let line = result_line(row, width);
+
+        match row.finished {
+            Some((0, _)) => eprintln!("{}", line.green()),
+            Some(_) => eprintln!("{}", line.red()),
+            None => eprintln!("{}", line.dim()),
+        }
+    }
+}
+
+/// The escape sequences for a terminal notification, followed by a bell
+/// - VTE based terminals (e.g. GNOME Terminal) and urxvt use OSC 777, most others OSC 9
+fn notification(title: &str, body: &str, osc_777: bool) -> String {
+    // Control characters would end the sequence early, and `;` separates the fields
+    let clean = |text: &str| text.replace(|c: char| c.is_control() || c == ';', " ");
+
+    if osc_777 {
+        format!("\x1b]777;notify;{};{}\x07\x07", clean(title), clean(body))
+    } else {
+        format!("\x1b]9;{}\x07\x07", clean(body))
+    }
+}
+
+/// Send a terminal notification for a finished run, so it is noticed when switched away
+/// - Only if stderr is a terminal, to not mess up redirected output
+pub fn notify_finished(cmd_str: &str, exit_code: i32, elapsed: Duration) {
+    let mut stderr = std::io::stderr();
+    if !stderr.is_terminal() {
+        return;
+    }
+
+    let osc_777 = std::env::var_os("VTE_VERSION").is_some()
+        || std::env::var("TERM").is_ok_and(|term| term.starts_with("rxvt"));
+
+    let title = match exit_code {
+        0 => "ds: finished",
+        _ => "ds: failed",
+    };
+
+    let body = summary_line(cmd_str, exit_code, elapsed);
+    let _ = stderr.write_all(notification(title, &body, osc_777).as_bytes());
+    let _ = stderr.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_lines() {
+        struct Case {
+            name: &'static str,
+            exit_code: i32,
+            elapsed: Duration,
+            expected: &'static str,
+        }
+
+        let cases = [
+            Case {
+                name: "Success",
+                exit_code: 0,
+                elapsed: Duration::from_micros(1_500_300),
+                expected: "✔ cargo build finished in 1s 500ms",
+            },
+            Case {
+                name: "Failure, rounded to seconds",
+                exit_code: 2,
+                elapsed: Duration::from_millis(603_250),
+                expected: "✘ cargo build failed with exit code 2 after 10m 3s",
+            },
+        ];
+
+        for case in cases {
+            assert_eq!(
+                summary_line("cargo build", case.exit_code, case.elapsed),
+                case.expected,
+                "{}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn result_lines() {
+        let rows = [
+            RunRow {
+                name: "lint".to_string(),
+                finished: Some((0, Duration::from_millis(1200))),
+            },
+            RunRow {
+                name: "test -- --nocapture".to_string(),
+                finished: Some((101, Duration::from_secs(3))),
+            },
+            RunRow {
+                name: "build".to_string(),
+                finished: None,
+            },
+        ];
+
+        let lines: Vec<String> = rows.iter().map(|row| result_line(row, 5)).collect();
+        assert_eq!(
+            lines,
+            [
+                "✔ lint   passed  1s 200ms",
+                "✘ test -- --nocapture  failed  3s (exit code 101)",
+                "- build  skipped",
+            ]
+        );
+    }
+
+    #[test]
+    fn notifications() {
+        assert_eq!(
+            notification("ds: finished", "done; ok\n", false),
+            "\x1b]9;done  ok \x07\x07"
+        );
+        assert_eq!(
+            notification("ds: failed", "build", true),
+            "\x1b]777;notify;ds: failed;build\x07\x07"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use crate::{config::ConfigDuration, dir::resolve_path};
+use anyhow::Result;
+use crossterm::style::Stylize;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command as ProcessCommand, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Default time to wait for a probe to succeed
+pub const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Interval between checks of a probe
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Time a single check may take at most, e.g. to connect to a port
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Interval to check if a check command is done
+const CHECK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What to wait for, until it is ready
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    /// A TCP port on localhost accepting connections
+    Port(u16),
+    /// A local `http://` URL responding with status 200
+    Url(String),
+    /// A file that has to exist, relative to the ds file
+    File(PathBuf),
+    /// A command that has to succeed, run like the command that waits for it, with its shell and env vars
+    Command(String),
+}
+
+/// Wait for a probe to succeed, e.g. a server to accept connections.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WaitFor {
+    #[serde(flatten)]
+    pub probe: Probe,
+    /// Optional time to give up after, defaults to 60 seconds.
+    pub timeout: Option<ConfigDuration>,
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Probe::Port(port) => write!(f, "port {}", port),
+            Probe::Url(url) => write!(f, "{}", url),
+            Probe::File(path) => write!(f, "file {}", path.display()),
+            Probe::Command(command) => write!(f, "`{}`", command),
+        }
+    }
+}
+
+/// A probe ready to be checked, with the process to run for a `command` probe
+#[derive(Debug)]
+pub struct ReadyCheck {
+    pub wait: WaitFor,
+    command: Option<ProcessCommand>,
+}
+
+impl ReadyCheck {
+    /// Prepare the probe, creating the process of a `command` probe with `create`,
+    /// e.g. with the env and shell of the command that waits for it
+    pub fn new(wait: WaitFor, create: impl FnOnce(&str) -> Result<ProcessCommand>) -> Result<Self> {
+        let command = match &wait.probe {
+            Probe::Command(command) => {
+                let mut cmd = create(command)?;
+                cmd.stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .process_group(0);
+                Some(cmd)
+            }
+            _ => None,
+        };
+
+        Ok(ReadyCheck { wait, command })
+    }
+
+    /// Check once if the probe succeeds
+    /// - A check command still running at the deadline is killed, and counts as failed
+    fn check(&mut self, deadline: Instant, file_path: &Path) -> Result<bool> {
+        match (&self.wait.probe, &mut self.command) {
+            (Probe::Port(port), _) => Ok(port_open(*port, deadline)),
+            (Probe::Url(url), _) => Ok(http_status(url, deadline)? == Some(200)),
+            (Probe::File(path), _) => Ok(resolve_path(path, file_path)?.exists()),
+            (Probe::Command(_), Some(cmd)) => {
+                let mut child = cmd.spawn()?;
+
+                loop {
+                    if let Some(status) = child.try_wait()? {
+                        return Ok(status.success());
+                    }
+
+                    if Instant::now() >= deadline {
+                        // SAFETY: Only kills the process group created for the check
+                        unsafe { libc::killpg(child.id() as i32, libc::SIGKILL) };
+                        let _ = child.wait();
+                        return Ok(false);
+                    }
+
+                    thread::sleep(CHECK_POLL_INTERVAL);
+                }
+            }
+            (Probe::Command(command), None) => Err(anyhow::anyhow!(
+                "No process prepared for the check command `{}`",
+                command
+            )),
+        }
+    }
+}
+
+/// Create the process of a check command with `sh`, for probes without a command to run them like, e.g. `ds wait --cmd`
+pub fn sh_command(command: &str) -> Result<ProcessCommand> {
+    let mut cmd = ProcessCommand::new("sh");
+    cmd.arg("-c").arg(command);
+    Ok(cmd)
+}
+
+/// Get the time the next step of a check may take, so a check never runs past the deadline
+/// - None once the deadline has passed
+fn check_timeout(deadline: Instant) -> Option<Duration> {
+    let left = deadline.saturating_duration_since(Instant::now());
+    (!left.is_zero()).then(|| left.min(CHECK_TIMEOUT))
+}
+
+/// Connect to the first address that accepts connections, trying them one by one until the deadline
+fn connect(addrs: impl IntoIterator<Item = SocketAddr>, deadline: Instant) -> Option<TcpStream> {
+    addrs.into_iter().find_map(|addr| {
+        let timeout = check_timeout(deadline)?;
+        TcpStream::connect_timeout(&addr, timeout).ok()
+    })
+}
+
+/// Check if anything accepts connections on the port on localhost, IPv4 or IPv6
+fn port_open(port: u16, deadline: Instant) -> bool {
+    let Ok(addrs) = ("localhost", port).to_socket_addrs() else {
+        return false;
+    };
+
+    connect(addrs, deadline).is_some()
+}
+
+/// Split a `http://host:port/path` URL into its address and path
+fn parse_url(url: &str) -> Result<(String, u16, String)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        anyhow::anyhow!("Only local http:// URLs can be waited for, got: {}", url)
+    })?;
+
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+
+    // The last colon separates the port, unless it is part of an IPv6 address like `[::1]`
+    let (host, port) = match authority.rfind(':') {
+        Some(index) if !authority[index..].contains(']') => {
+            let port = authority[index + 1..]
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid port in URL: {}", url))?;
+            (&authority[..index], port)
+        }
+        _ => (authority, 80),
+    };
+
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// Get the status code of a GET request, or None if the server can't be reached (yet)
+/// - Gives up at the deadline, e.g. when the server accepts the connection but doesn't respond
+fn http_status(url: &str, deadline: Instant) -> Result<Option<u16>> {
+    let (host, port, path) = parse_url(url)?;
+
+    let Ok(addrs) = (host.trim_start_matches('[').trim_end_matches(']'), port).to_socket_addrs()
+    else {
+        return Ok(None);
+    };
+
+    // Try every address, e.g. `localhost` often resolves to `::1` first, while the server is on IPv4
+    let Some(mut stream) = connect(addrs, deadline) else {
+        return Ok(None);
+    };
+
+    let Some(timeout) = check_timeout(deadline) else {
+        return Ok(None);
+    };
+    let _ = stream.set_read_timeout(Some(timeout));
+    let _ = stream.set_write_timeout(Some(timeout));
+
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    if stream.write_all(request.as_bytes()).is_err() {
+        return Ok(None);
+    }
+
+    // Only the status line is needed, e.g. `HTTP/1.1 200 OK`
+    let mut buf = [0; 64];
+    let mut read = 0;
+    while read < buf.len() && !buf[..read].contains(&b'\n') {
+        match stream.read(&mut buf[read..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => read += n,
+        }
+    }
+
+    let status = String::from_utf8_lossy(&buf[..read])
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok());
+
+    Ok(status)
+}
+
+/// Wait for all probes to succeed, one after the other
+/// - Returns a message naming the probe if one of them times out
+/// - Returns an error if a probe can't be checked, e.g. an invalid URL or a check command
+///   that can't be started
+pub fn wait_for(checks: &mut [ReadyCheck], file_path: &Path) -> Result<Option<String>> {
+    for check in checks {
+        let timeout = check.wait.timeout.map_or(DEFAULT_WAIT_TIMEOUT, |d| d.0);
+        let deadline = Instant::now() + timeout;
+
+        if check.check(deadline, file_path)? {
+            continue;
+        }
+
+        println!("{}", format!("Waiting for {}", check.wait.probe).dim());
+
+        loop {
+            if Instant::now() >= deadline {
+                return Ok(Some(format!(
+                    "Timed out after {} waiting for {}",
+                    humantime::format_duration(timeout),
+                    check.wait.probe
+                )));
+            }
+
+            thread::sleep(POLL_INTERVAL);
+
+            if check.check(deadline, file_path)? {
+                break;
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Parse the probes of `ds wait --port <port> --url <url> --file <path> --cmd <command> [--timeout <duration>]`
+/// - The timeout applies to all probes
+pub fn parse_probes(args: &[&str]) -> Result<Vec<WaitFor>> {
+    let mut probes = Vec::new();
+    let mut timeout = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!("Missing value for option '{}'", arg))
+        };
+
+        let probe = match *arg {
+            "--port" => {
+                let port = value()?;
+                Probe::Port(
+                    port.parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid port: {}", port))?,
+                )
+            }
+            "--url" => Probe::Url(value()?.to_string()),
+            // Relative to the current directory, as there is no ds file
+            "--file" => Probe::File(std::path::absolute(value()?)?),
+            "--cmd" => Probe::Command(value()?.to_string()),
+            "--timeout" => {
+                timeout = Some(value()?.parse()?);
+                continue;
+            }
+            _ => return Err(anyhow::anyhow!("Unknown option for ds wait: {}", arg)),
+        };
+
+        probes.push(WaitFor {
+            probe,
+            timeout: None,
+        });
+    }
+
+    for probe in &mut probes {
+        probe.timeout = timeout;
+    }
+
+    Ok(probes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn parse_urls() {
+        struct Case {
+            name: &'static str,
+            url: &'static str,
+            expected: Option<(&'static str, u16, &'static str)>,
+        }
+
+        let cases = [
+            Case {
+                name: "Host and port",
+                url: "http://localhost:3000/health",
+                expected: Some(("localhost", 3000, "/health")),
+            },
+            Case {
+                name: "Default port and path",
+                url: "http://127.0.0.1",
+                expected: Some(("127.0.0.1", 80, "/")),
+            },
+            Case {
+                name: "IPv6",
+                url: "http://[::1]:8080/",
+                expected: Some(("[::1]", 8080, "/")),
+            },
+            Case {
+                name: "IPv6 without port",
+                url: "http://[::1]",
+                expected: Some(("[::1]", 80, "/")),
+            },
+            Case {
+                name: "HTTPS is not supported",
+                url: "https://localhost/",
+                expected: None,
+            },
+        ];
+
+        for case in cases {
+            let parsed = parse_url(case.url).ok();
+            let parsed = parsed
+                .as_ref()
+                .map(|(host, port, path)| (host.as_str(), *port, path.as_str()));
+            assert_eq!(parsed, case.expected, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn deserialize_probes() {
+        let wait: WaitFor = serde_json::from_str(r#"{"port": 5432, "timeout": "30s"}"#).unwrap();
+        assert_eq!(wait.probe, Probe::Port(5432));
+        assert_eq!(wait.timeout, Some(ConfigDuration(Duration::from_secs(30))));
+
+        let wait: WaitFor = serde_json::from_str(r#"{"command": "pg_isready"}"#).unwrap();
+        assert_eq!(wait.probe, Probe::Command("pg_isready".to_string()));
+        assert_eq!(wait.timeout, None);
+    }
+
+    #[test]
+    fn wait_for_probes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Respond to a single request with 200
+        thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+
+        let file_path = Path::new("./tests/fixtures/shells.json");
+        let url = format!("http://127.0.0.1:{}/health", port);
+        let deadline = Instant::now() + DEFAULT_WAIT_TIMEOUT;
+        assert_eq!(http_status(&url, deadline).unwrap(), Some(200));
+
+        // A server that accepts connections but never responds is given up on at the deadline
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/health", silent.local_addr().unwrap());
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(300);
+        assert_eq!(http_status(&url, deadline).unwrap(), None);
+        assert!(start.elapsed() < CHECK_TIMEOUT);
+
+        let checks = |args: &[&str]| -> Vec<ReadyCheck> {
+            parse_probes(args)
+                .unwrap()
+                .into_iter()
+                .map(|wait| ReadyCheck::new(wait, sh_command).unwrap())
+                .collect()
+        };
+
+        let mut probes = checks(&["--file", "tests/fixtures/shells.json", "--cmd", "true"]);
+        assert_eq!(wait_for(&mut probes, file_path).unwrap(), None);
+
+        let mut probes = checks(&["--cmd", "false", "--timeout", "300ms"]);
+        let timed_out = wait_for(&mut probes, file_path).unwrap();
+        assert_eq!(
+            timed_out.as_deref(),
+            Some("Timed out after 300ms waiting for `false`")
+        );
+
+        // A hanging check command is killed when the time runs out
+        let start = Instant::now();
+        let mut probes = checks(&["--cmd", "sleep 30", "--timeout", "300ms"]);
+        assert!(wait_for(&mut probes, file_path).unwrap().is_some());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // Probes that can't be checked fail right away, instead of timing out
+        let mut probes = checks(&["--url", "https://example.com", "--timeout", "1m"]);
+        let err = wait_for(&mut probes, file_path).unwrap_err();
+        assert!(err.to_string().contains("Only local http:// URLs"));
+
+        // Like the global `--timeout`, a bare number is in seconds
+        let probes = parse_probes(&["--port", "1", "--timeout", "30"]).unwrap();
+        assert_eq!(
+            probes[0].timeout,
+            Some(ConfigDuration(Duration::from_secs(30)))
+        );
+    }
+}
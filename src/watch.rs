@@ -0,0 +1,295 @@
+use crate::{
+    command::RetryConfig,
+    logs::{RunLog, run_logged},
+    process::{self, ProcessOptions, ProcessOutcome},
+};
+use anyhow::Result;
+use crossterm::style::Stylize;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{
+    path::{Component, Path, PathBuf},
+    process::Command as ProcessCommand,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Time without changes to wait for, before the command is re-run
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Interval to check for the previous run to stop, while a re-run is pending
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Decides which changed files re-run the command
+pub struct WatchFilter {
+    root: PathBuf,
+    globs: Option<GlobSet>,
+    gitignore: Option<Gitignore>,
+}
+
+impl WatchFilter {
+    /// Create a filter for the globs, relative to the root
+    /// - Without globs, any file in the root matches
+    /// - Paths in the `.gitignore` of the git root are ignored
+    pub fn new(root: &Path, globs: Option<&[String]>, git_root: Option<&Path>) -> Result<Self> {
+        let root = root.canonicalize()?;
+
+        let globs = match globs {
+            Some(globs) => {
+                let mut builder = GlobSetBuilder::new();
+                for glob in globs {
+                    builder.add(GlobBuilder::new(glob).literal_separator(true).build()?);
+                }
+                Some(builder.build()?)
+            }
+            None => None,
+        };
+
+        let gitignore = match git_root.and_then(|dir| dir.canonicalize().ok()) {
+            Some(git_root) if git_root.join(".gitignore").is_file() => {
+                let mut builder = GitignoreBuilder::new(&git_root);
+                if let Some(err) = builder.add(git_root.join(".gitignore")) {
+                    return Err(err.into());
+                }
+                Some(builder.build()?)
+            }
+            _ => None,
+        };
+
+        Ok(WatchFilter {
+            root,
+            globs,
+            gitignore,
+        })
+    }
+
+    /// Check if a change to the path should re-run the command
+    pub fn is_match(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+
+        if relative
+            .components()
+            .any(|c| c == Component::Normal(".git".as_ref()))
+        {
+            return false;
+        }
+
+        if let Some(gitignore) = &self.gitignore
+            && path.starts_with(gitignore.path())
+            && gitignore
+                .matched_path_or_any_parents(path, path.is_dir())
+                .is_ignore()
+        {
+            return false;
+        }
+
+        self.globs
+            .as_ref()
+            .is_none_or(|globs| globs.is_match(relative))
+    }
+}
+
+/// Events the watch loop reacts to
+enum WatchEvent {
+    /// A watched file changed
+    Changed,
+    /// The run finished, handing back the command to re-run it
+    Finished(Box<ProcessCommand>, Result<ProcessOutcome>),
+    /// The file watcher failed
+    Failed(notify::Error),
+}
+
+/// Run the command in the background, sending the outcome when it is done
+fn spawn_run(
+    mut cmd: Box<ProcessCommand>,
+    cmd_str: &str,
+    options: ProcessOptions,
+    retry: Option<RetryConfig>,
+    log: Option<RunLog>,
+    sender: mpsc::Sender<WatchEvent>,
+) {
+    println!("{}", cmd_str.dim());
+
+    thread::spawn(move || {
+        let outcome = run_logged(&mut cmd, options, retry.as_ref(), log.as_ref());
+        let _ = sender.send(WatchEvent::Finished(cmd, outcome));
+    });
+}
+
+/// Run the command, and re-run it whenever a matching file changes
+/// - Changes are debounced, so saving many files at once only re-runs it once
+/// - The process group of the previous run is terminated before it is re-run
+/// - Stops when the command is interrupted, e.g. with Ctrl+C
+pub fn watch(
+    cmd: Box<ProcessCommand>,
+    cmd_str: &str,
+    options: ProcessOptions,
+    retry: Option<RetryConfig>,
+    log: Option<RunLog>,
+    filter: WatchFilter,
+) -> Result<i32> {
+    let (sender, events) = mpsc::channel();
+
+    let root = filter.root.clone();
+    let watch_sender = sender.clone();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let event = match res {
+            Ok(event) if matches!(event.kind, EventKind::Access(_) | EventKind::Other) => return,
+            Ok(event) if event.paths.iter().any(|path| filter.is_match(path)) => {
+                WatchEvent::Changed
+            }
+            Ok(_) => return,
+            Err(err) => WatchEvent::Failed(err),
+        };
+        let _ = watch_sender.send(event);
+    })?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+
+    spawn_run(
+        cmd,
+        cmd_str,
+        options,
+        retry.clone(),
+        log.clone(),
+        sender.clone(),
+    );
+
+    // The command while it is not running, and when the last change was seen if a re-run is pending
+    let mut idle = None;
+    let mut pending: Option<Instant> = None;
+
+    loop {
+        if let Some(changed_at) = pending
+            && changed_at.elapsed() >= DEBOUNCE
+        {
+            match idle.take() {
+                Some(cmd) => {
+                    pending = None;
+                    spawn_run(
+                        cmd,
+                        cmd_str,
+                        options,
+                        retry.clone(),
+                        log.clone(),
+                        sender.clone(),
+                    );
+                }
+                None => process::stop_all(options.grace_period),
+            }
+        }
+
+        let event = if pending.is_some() {
+            match events.recv_timeout(POLL_INTERVAL) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match events.recv() {
+                Ok(event) => event,
+                Err(_) => break,
+            }
+        };
+
+        match event {
+            WatchEvent::Changed => pending = Some(Instant::now()),
+            WatchEvent::Finished(cmd, outcome) => {
+                let outcome = outcome?;
+
+                // Stopped by the user, instead of for a re-run
+                if outcome.interrupted && pending.is_none() {
+                    return Ok(outcome.exit_code());
+                }
+
+                if pending.is_none() {
+                    let status = match outcome.exit_code() {
+                        0 => "Finished, waiting for changes...".to_string(),
+                        code => format!("Exited with code {}, waiting for changes...", code),
+                    };
+                    eprintln!("{}", status.dim());
+                }
+
+                idle = Some(cmd);
+            }
+            WatchEvent::Failed(err) => return Err(err.into()),
+        }
+    }
+
+    Err(anyhow::anyhow!("Stopped watching for changes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_filter() {
+        struct Case {
+            name: &'static str,
+            globs: Option<Vec<&'static str>>,
+            path: &'static str,
+            expected: bool,
+        }
+
+        let cases = [
+            Case {
+                name: "Any file without globs",
+                globs: None,
+                path: "README.md",
+                expected: true,
+            },
+            Case {
+                name: "Ignored by .gitignore",
+                globs: None,
+                path: "target/debug/ds",
+                expected: false,
+            },
+            Case {
+                name: "Git directory",
+                globs: None,
+                path: ".git/index",
+                expected: false,
+            },
+            Case {
+                name: "Matching glob",
+                globs: Some(vec!["src/**/*.rs"]),
+                path: "src/main.rs",
+                expected: true,
+            },
+            Case {
+                name: "Not matching glob",
+                globs: Some(vec!["src/**/*.rs", "Cargo.toml"]),
+                path: "README.md",
+                expected: false,
+            },
+            Case {
+                name: "Outside the root",
+                globs: None,
+                path: "../README.md",
+                expected: false,
+            },
+        ];
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .canonicalize()
+            .unwrap();
+
+        for case in cases {
+            let globs: Option<Vec<String>> = case
+                .globs
+                .map(|globs| globs.iter().map(|g| g.to_string()).collect());
+            let filter = WatchFilter::new(&root, globs.as_deref(), Some(&root)).unwrap();
+
+            let path = match case.path.strip_prefix("../") {
+                Some(path) => root.parent().unwrap().join(path),
+                None => root.join(case.path),
+            };
+
+            assert_eq!(filter.is_match(&path), case.expected, "{}", case.name);
+        }
+    }
+}